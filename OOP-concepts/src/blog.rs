@@ -9,22 +9,51 @@ pub struct Post {
     // value changes based on internal state (Draft, PendingReview, Published)
     // changes are dynamically dispatched.
     state: Option<Box<dyn State>>,
+    body: Body,
+}
+
+// What a post is made of, whatever its state. Both Post and the typestate posts further down keep their
+// text in one of these, so turning one into the other moves it across rather than copying it.
+struct Body {
     content: String,
+    // every reason a reviewer gave for sending the post back to Draft, oldest first
+    rejections: Vec<String>,
+    // how many different reviewers have to approve the post before it's published
+    quorum: usize,
+}
+
+impl Body {
+    // Like Guess::new in ch.9, a quorum of 0 is a bug in the caller: nobody approving can't publish a post.
+    fn new(quorum: usize) -> Body {
+        if quorum == 0 {
+            panic!("a post needs at least one approval to be published, got a quorum of 0");
+        }
+        Body {
+            content: String::new(),
+            rejections: Vec::new(),
+            quorum,
+        }
+    }
 }
 
 impl Post {
     pub fn new() -> Post {
+        Post::with_quorum(1)
+    }
+
+    // A post that needs `approvals` reviewers to approve it before it's published. Panics if that's 0.
+    pub fn with_quorum(approvals: usize) -> Post {
         // This ensures whenever we create a new instance of Post, it will start out as a draft.
         // Because the state field of Post is private, there is no way to create a Post in any other state.
         Post {
             state: Some(Box::new(Draft {})),
-            content: String::new(),
+            body: Body::new(approvals),
         }
     }
 
     // mutable because we're changing the Post instance we're calling on
     pub fn add_content(&mut self, text: &str) {
-        self.content.push_str(text);
+        self.body.content.push_str(text);
     }
 
     // gets a reference to self (Post struct) and call use_ref on the Option
@@ -47,11 +76,36 @@ impl Post {
         }
     }
 
-    pub fn approve(&mut self) {
+    // Approvals are counted per reviewer, so the same reviewer approving twice is still one approval.
+    pub fn approve(&mut self, reviewer: &str) {
+        if let Some(s) = self.state.take() {
+            self.state = Some(s.approve(reviewer, self.body.quorum))
+        }
+    }
+
+    // the reviewers who have approved the post under review so far, or who approved it once it's published
+    pub fn approvals(&self) -> &[String] {
+        self.state.as_ref().unwrap().approvals()
+    }
+
+    // A reviewer sends the post back with a reason. Whether that does anything is up to the state,
+    // so the reason is handed over to it along with the history to record it in.
+    pub fn reject(&mut self, reason: &str) {
         if let Some(s) = self.state.take() {
-            self.state = Some(s.approve())
+            self.state = Some(s.reject(reason, &mut self.body.rejections))
         }
     }
+
+    // the reasons the post was rejected for, so the author can see what to change
+    pub fn rejections(&self) -> &[String] {
+        &self.body.rejections
+    }
+}
+
+impl Default for Post {
+    fn default() -> Post {
+        Post::new()
+    }
 }
 
 // This syntax means the method is only valid when called on a Box holding the type.
@@ -59,29 +113,53 @@ impl Post {
 // value of the Post can transform into a new state.
 trait State {
     fn request_review(self: Box<Self>) -> Box<dyn State>;
-    fn content<'a>(&self, post: &'a Post) -> &'a str {
+    fn content<'a>(&self, _post: &'a Post) -> &'a str {
         ""
     }
-    fn approve(self: Box<Self>) -> Box<dyn State>;
+    fn approve(self: Box<Self>, reviewer: &str, quorum: usize) -> Box<dyn State>;
+    fn approvals(&self) -> &[String] {
+        &[]
+    }
+    // only a state that accepts the rejection records its reason
+    fn reject(self: Box<Self>, reason: &str, rejections: &mut Vec<String>) -> Box<dyn State>;
+    // each state knows which of the typestate posts it is (see TypedPost)
+    fn into_typed(self: Box<Self>, body: Body) -> TypedPost;
 }
 
 // our state objects
 struct Draft {}
-struct PendingReview {}
-struct Published {}
+struct PendingReview {
+    // the tally belongs to this review: a rejection goes back to Draft, and the next review starts from nothing
+    approvals: Vec<String>,
+}
+struct Published {
+    // who approved it, kept now that it's out
+    approvals: Vec<String>,
+}
 
 // our state implementations
 impl State for Draft {
     // The request_review method on Draft needs to return a new, boxed instance of a new PendingReview struct,
     // which represents the state when a post is waiting for a review.
     fn request_review(self: Box<Self>) -> Box<dyn State> {
-        Box::new(PendingReview {})
+        Box::new(PendingReview {
+            approvals: Vec::new(),
+        })
     }
 
     // if we call the approve method on a Draft, it will have no effect
-    fn approve(self: Box<Self>) -> Box<dyn State> {
+    fn approve(self: Box<Self>, _reviewer: &str, _quorum: usize) -> Box<dyn State> {
         self
     }
+
+    // nobody has been asked to review a draft yet, so there's nothing to reject
+    fn reject(self: Box<Self>, _reason: &str, _rejections: &mut Vec<String>) -> Box<dyn State> {
+        self
+    }
+
+    fn into_typed(self: Box<Self>, body: Body) -> TypedPost {
+        TypedPost::Draft(DraftPost { body })
+    }
 }
 
 impl State for PendingReview {
@@ -92,27 +170,349 @@ impl State for PendingReview {
         self
     }
 
-    // When we call approve on PendingReview, it returns a new, boxed instance of the Published struct.
-    fn approve(self: Box<Self>) -> Box<dyn State> {
-        Box::new(Published {})
+    // When we call approve on PendingReview, it records the reviewer, and once enough different reviewers
+    // have approved it returns a new, boxed instance of the Published struct.
+    fn approve(mut self: Box<Self>, reviewer: &str, quorum: usize) -> Box<dyn State> {
+        if !self.approvals.iter().any(|r| r == reviewer) {
+            self.approvals.push(reviewer.to_string());
+        }
+        if self.approvals.len() >= quorum {
+            Box::new(Published {
+                approvals: self.approvals,
+            })
+        } else {
+            self
+        }
+    }
+
+    fn approvals(&self) -> &[String] {
+        &self.approvals
+    }
+
+    fn into_typed(self: Box<Self>, body: Body) -> TypedPost {
+        TypedPost::PendingReview(PendingReviewPost {
+            body,
+            approvals: self.approvals,
+        })
+    }
+
+    // Rejecting sends the post back to Draft, so the author can change it and request a review again.
+    fn reject(self: Box<Self>, reason: &str, rejections: &mut Vec<String>) -> Box<dyn State> {
+        rejections.push(reason.to_string());
+        Box::new(Draft {})
     }
 }
 
-// The Published struct implements the State trait, and for both the request_review method and the approve method,
+// The Published struct implements the State trait, and for the request_review, approve and reject methods
 // it returns itself, because the post should stay in the Published state in those cases.
 impl State for Published {
     fn request_review(self: Box<Self>) -> Box<dyn State> {
         self
     }
 
-    fn approve(self: Box<Self>) -> Box<dyn State> {
+    fn approve(self: Box<Self>, _reviewer: &str, _quorum: usize) -> Box<dyn State> {
         self
     }
 
+    // a post that's already out can't be sent back for changes
+    fn reject(self: Box<Self>, _reason: &str, _rejections: &mut Vec<String>) -> Box<dyn State> {
+        self
+    }
+
+    fn approvals(&self) -> &[String] {
+        &self.approvals
+    }
+
+    fn into_typed(self: Box<Self>, body: Body) -> TypedPost {
+        TypedPost::Published(PublishedPost {
+            body,
+            approvals: self.approvals,
+        })
+    }
+
     // Note that we need lifetime annotations on this method, as we discussed in Ch 10.
     // We’re taking a reference to a post as an argument and returning a reference to part of that post,
     // so the lifetime of the returned reference is related to the lifetime of the post argument.
     fn content<'a>(&self, post: &'a Post) -> &'a str {
-        &post.content
+        &post.body.content
+    }
+}
+
+/*
+The same workflow with the states encoded as types instead of state objects: each state is its own struct,
+ and a transition consumes the post and returns one of another type. Asking a draft for its content, or
+  approving a post nobody asked to review, doesn't compile, rather than quietly doing nothing at run time.
+   The trade-off is that the caller has to keep track of which type they're holding, i.e. let post = post.request_review();
+    Posts can be turned from one representation into the other, so both can be used in the same program.
+*/
+pub struct DraftPost {
+    body: Body,
+}
+
+pub struct PendingReviewPost {
+    body: Body,
+    approvals: Vec<String>,
+}
+
+pub struct PublishedPost {
+    body: Body,
+    approvals: Vec<String>,
+}
+
+impl DraftPost {
+    pub fn new() -> DraftPost {
+        DraftPost::with_quorum(1)
+    }
+
+    pub fn with_quorum(approvals: usize) -> DraftPost {
+        DraftPost {
+            body: Body::new(approvals),
+        }
+    }
+
+    pub fn add_content(&mut self, text: &str) {
+        self.body.content.push_str(text);
+    }
+
+    pub fn rejections(&self) -> &[String] {
+        &self.body.rejections
+    }
+
+    pub fn request_review(self) -> PendingReviewPost {
+        PendingReviewPost {
+            body: self.body,
+            approvals: Vec::new(),
+        }
+    }
+}
+
+impl Default for DraftPost {
+    fn default() -> DraftPost {
+        DraftPost::new()
+    }
+}
+
+impl PendingReviewPost {
+    // Published once enough different reviewers have approved, otherwise still pending (as Err),
+    // with this reviewer counted.
+    pub fn approve(mut self, reviewer: &str) -> Result<PublishedPost, PendingReviewPost> {
+        if !self.approvals.iter().any(|r| r == reviewer) {
+            self.approvals.push(reviewer.to_string());
+        }
+        if self.approvals.len() >= self.body.quorum {
+            Ok(PublishedPost {
+                body: self.body,
+                approvals: self.approvals,
+            })
+        } else {
+            Err(self)
+        }
+    }
+
+    pub fn approvals(&self) -> &[String] {
+        &self.approvals
+    }
+
+    // back to a draft, and the approvals so far are dropped with this review
+    pub fn reject(mut self, reason: &str) -> DraftPost {
+        self.body.rejections.push(reason.to_string());
+        DraftPost { body: self.body }
+    }
+}
+
+impl PublishedPost {
+    // only a published post has a content method at all
+    pub fn content(&self) -> &str {
+        &self.body.content
+    }
+
+    // the reviewers who approved it
+    pub fn approvals(&self) -> &[String] {
+        &self.approvals
+    }
+}
+
+// A Post turned into a typestate post: which type it becomes depends on the state it's in at run time.
+pub enum TypedPost {
+    Draft(DraftPost),
+    PendingReview(PendingReviewPost),
+    Published(PublishedPost),
+}
+
+impl From<Post> for TypedPost {
+    fn from(post: Post) -> TypedPost {
+        post.state.unwrap().into_typed(post.body)
+    }
+}
+
+// and the other way: a typestate post becomes a Post in the matching state
+impl From<DraftPost> for Post {
+    fn from(post: DraftPost) -> Post {
+        Post {
+            state: Some(Box::new(Draft {})),
+            body: post.body,
+        }
+    }
+}
+
+impl From<PendingReviewPost> for Post {
+    fn from(post: PendingReviewPost) -> Post {
+        Post {
+            state: Some(Box::new(PendingReview {
+                approvals: post.approvals,
+            })),
+            body: post.body,
+        }
+    }
+}
+
+impl From<PublishedPost> for Post {
+    fn from(post: PublishedPost) -> Post {
+        Post {
+            state: Some(Box::new(Published {
+                approvals: post.approvals,
+            })),
+            body: post.body,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending() -> Post {
+        let mut post = Post::new();
+        post.add_content("I ate a salad for lunch today");
+        post.request_review();
+        post
+    }
+
+    #[test]
+    fn rejecting_a_draft_does_nothing() {
+        let mut post = Post::new();
+        post.add_content("draft");
+        post.reject("too short");
+
+        assert!(post.rejections().is_empty());
+        // still a draft: it has to be reviewed before it can be approved
+        post.approve("alice");
+        assert_eq!("", post.get_content());
+    }
+
+    #[test]
+    fn rejecting_a_pending_post_sends_it_back_to_draft() {
+        let mut post = pending();
+        post.reject("needs a photo");
+
+        assert_eq!(["needs a photo"], post.rejections());
+        post.approve("alice");
+        assert_eq!("", post.get_content());
+
+        // after another review it can be rejected again, or approved
+        post.request_review();
+        post.reject("photo is blurry");
+        post.request_review();
+        post.approve("alice");
+        assert_eq!("I ate a salad for lunch today", post.get_content());
+        assert_eq!(["needs a photo", "photo is blurry"], post.rejections());
+    }
+
+    #[test]
+    fn rejecting_a_published_post_does_nothing() {
+        let mut post = pending();
+        post.approve("alice");
+        post.reject("too late");
+
+        assert!(post.rejections().is_empty());
+        assert_eq!("I ate a salad for lunch today", post.get_content());
+    }
+
+    #[test]
+    fn quorum_needs_different_reviewers() {
+        let mut post = Post::with_quorum(2);
+        post.add_content("I ate a salad for lunch today");
+        post.request_review();
+
+        post.approve("alice");
+        // approving twice doesn't make alice two reviewers
+        post.approve("alice");
+        assert_eq!(["alice"], post.approvals());
+        assert_eq!("", post.get_content());
+
+        post.approve("bob");
+        assert_eq!("I ate a salad for lunch today", post.get_content());
+    }
+
+    #[test]
+    fn rejection_resets_the_tally() {
+        let mut post = Post::with_quorum(2);
+        post.add_content("I ate a salad for lunch today");
+        post.request_review();
+        post.approve("alice");
+        post.reject("typo in the title");
+
+        post.request_review();
+        assert!(post.approvals().is_empty());
+        post.approve("bob");
+        assert_eq!(["bob"], post.approvals());
+        assert_eq!("", post.get_content());
+        post.approve("alice");
+        assert_eq!("I ate a salad for lunch today", post.get_content());
+        // published, and the approvals of this review are still there
+        assert_eq!(["bob", "alice"], post.approvals());
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one approval")]
+    fn quorum_of_zero_is_rejected() {
+        Post::with_quorum(0);
+    }
+
+    #[test]
+    fn typestate_workflow() {
+        let mut post = DraftPost::with_quorum(2);
+        post.add_content("I ate a salad for lunch today");
+        let post = post.request_review().reject("which salad?");
+        assert_eq!(["which salad?"], post.rejections());
+
+        let post = match post.request_review().approve("alice") {
+            Ok(_) => panic!("one approval isn't a quorum of two"),
+            Err(pending) => pending,
+        };
+        assert_eq!(["alice"], post.approvals());
+        let post = match post.approve("bob") {
+            Ok(published) => published,
+            Err(_) => panic!("two approvals are a quorum"),
+        };
+        assert_eq!("I ate a salad for lunch today", post.content());
+        assert_eq!(["alice", "bob"], post.approvals());
+    }
+
+    #[test]
+    fn converts_between_post_and_typestate() {
+        // halfway through a review as a Post, finished as a typestate post
+        let mut post = Post::with_quorum(2);
+        post.add_content("I ate a salad for lunch today");
+        post.request_review();
+        post.approve("alice");
+        let pending = match TypedPost::from(post) {
+            TypedPost::PendingReview(pending) => pending,
+            _ => panic!("the post was under review"),
+        };
+        assert_eq!(["alice"], pending.approvals());
+
+        // and back: the approval is still counted, one more publishes it
+        let mut post = Post::from(pending);
+        post.approve("bob");
+        assert_eq!("I ate a salad for lunch today", post.get_content());
+        let published = match TypedPost::from(post) {
+            TypedPost::Published(published) => published,
+            _ => panic!("two approvals are a quorum"),
+        };
+        assert_eq!(["alice", "bob"], published.approvals());
+
+        let post = Post::from(DraftPost::new());
+        assert!(matches!(TypedPost::from(post), TypedPost::Draft(_)));
     }
 }
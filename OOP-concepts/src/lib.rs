@@ -1,3 +1,6 @@
+// the package is named OOP-concepts, which makes the crate OOP_concepts
+#![allow(non_snake_case)]
+
 // Rust is object oriented: structs and enums have data, and impl blocks provide methods on structs and enums.
 pub struct AveragedCollection {
     list: Vec<i32>,
@@ -1,7 +1,10 @@
+// the package is named OOP-concepts, which makes the crate OOP_concepts
+#![allow(non_snake_case)]
+
 use OOP_concepts::{Button, Screen};
 
 mod blog;
-pub use crate::blog::Post;
+pub use crate::blog::{DraftPost, Post, TypedPost};
 
 fn main() {
     // By specifying Box<dyn Draw> as the type of the values in the components vector,
@@ -32,6 +35,35 @@ fn main() {
     post.request_review();
     assert_eq!("", post.get_content());
 
-    post.approve();
+    // a reviewer can send it back to Draft with a reason, and it has to be reviewed again
+    post.reject("Which salad?");
+    post.request_review();
+    assert_eq!(["Which salad?"], post.rejections());
+
+    post.approve("alice");
     assert_eq!("I ate a salad for lunch today", post.get_content());
+
+    // a post can need more than one reviewer, each of them only counted once
+    let mut post = Post::with_quorum(2);
+    post.add_content("I ate a sandwich for dinner");
+    post.request_review();
+    post.approve("alice");
+    post.approve("alice");
+    assert_eq!(["alice"], post.approvals());
+    post.approve("bob");
+    assert_eq!("I ate a sandwich for dinner", post.get_content());
+
+    // the typestate version: every transition returns a post of another type, and only a PublishedPost
+    // has a content method, so asking a draft for its content doesn't compile
+    let mut post = DraftPost::new();
+    post.add_content("I ate a salad for lunch today");
+    let post = post.request_review().reject("Which salad?");
+    assert_eq!(["Which salad?"], post.rejections());
+    if let Ok(post) = post.request_review().approve("alice") {
+        assert_eq!("I ate a salad for lunch today", post.content());
+
+        // either representation can be turned into the other
+        let post = Post::from(post);
+        assert!(matches!(TypedPost::from(post), TypedPost::Published(_)));
+    }
 }
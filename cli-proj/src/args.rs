@@ -0,0 +1,488 @@
+// Command line parsing: a table of the options cli-proj understands, a parser that turns
+// the raw args into (option, value) pairs plus positionals, and the --help text generated from the table.
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub struct Opt {
+    pub short: Option<char>,
+    pub long: &'static str,
+    // the name shown in --help for an option that takes a value, None for plain on/off flags
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+// Adding an option means adding it here (so it's parsed and shows up in --help) and handling it in Config::new.
+pub const OPTIONS: &[Opt] = &[
+    Opt {
+        short: Some('E'),
+        long: "regex",
+        value: None,
+        help: "treat QUERY as a regular expression",
+    },
+    Opt {
+        short: Some('e'),
+        long: "pattern",
+        value: Some("PATTERN"),
+        help: "search for PATTERN instead of QUERY (can be repeated, lines matching any of them are selected)",
+    },
+    Opt {
+        short: Some('f'),
+        long: "file",
+        value: Some("FILE"),
+        help: "search for each line of FILE as a pattern (can be combined with -e)",
+    },
+    Opt {
+        short: None,
+        long: "show-pattern",
+        value: None,
+        help: "print the patterns found on each line before it, i.e. path:foo,bar:text",
+    },
+    Opt {
+        short: Some('w'),
+        long: "word-regexp",
+        value: None,
+        help: "only match whole words (no letter, digit or _ right before or after)",
+    },
+    Opt {
+        short: Some('x'),
+        long: "line-regexp",
+        value: None,
+        help: "only match whole lines",
+    },
+    Opt {
+        short: None,
+        long: "fuzzy",
+        value: Some("NUM"),
+        help: "also match QUERY with up to NUM typos (chars inserted, deleted or changed)",
+    },
+    Opt {
+        short: None,
+        long: "sort-distance",
+        value: None,
+        help: "with --fuzzy, print each file's lines closest match first",
+    },
+    Opt {
+        short: None,
+        long: "only",
+        value: Some("KIND"),
+        help: "search .rs files as Rust, only matching inside identifier, string or comment (can be repeated)",
+    },
+    Opt {
+        short: Some('i'),
+        long: "ignore-case",
+        value: None,
+        help: "match without regard to case (same as CASE_INSENSITIVE=1)",
+    },
+    Opt {
+        short: Some('s'),
+        long: "case-sensitive",
+        value: None,
+        help: "match case exactly, even if CASE_INSENSITIVE is set",
+    },
+    Opt {
+        short: Some('v'),
+        long: "invert-match",
+        value: None,
+        help: "select the lines that don't match",
+    },
+    Opt {
+        short: Some('c'),
+        long: "count",
+        value: None,
+        help: "print only the number of selected lines per file",
+    },
+    Opt {
+        short: Some('l'),
+        long: "files-with-matches",
+        value: None,
+        help: "print only the names of files with selected lines",
+    },
+    Opt {
+        short: Some('L'),
+        long: "files-without-match",
+        value: None,
+        help: "print only the names of files without selected lines",
+    },
+    Opt {
+        short: Some('a'),
+        long: "text",
+        value: None,
+        help: "print matching lines of binary files too, instead of \"Binary file X matches\"",
+    },
+    Opt {
+        short: None,
+        long: "json",
+        value: None,
+        help: "print results as JSON Lines (one object per match, plus begin/end/summary)",
+    },
+    Opt {
+        short: None,
+        long: "color",
+        value: Some("WHEN"),
+        help: "highlight matches: auto (if stdout is a terminal and NO_COLOR isn't set), always or never",
+    },
+    Opt {
+        short: None,
+        long: "column",
+        value: None,
+        help: "print each match as path:line:column:text",
+    },
+    Opt {
+        short: Some('A'),
+        long: "after-context",
+        value: Some("NUM"),
+        help: "print NUM lines after each match",
+    },
+    Opt {
+        short: Some('B'),
+        long: "before-context",
+        value: Some("NUM"),
+        help: "print NUM lines before each match",
+    },
+    Opt {
+        short: Some('C'),
+        long: "context",
+        value: Some("NUM"),
+        help: "print NUM lines before and after each match",
+    },
+    Opt {
+        short: Some('j'),
+        long: "threads",
+        value: Some("NUM"),
+        help: "search NUM files at once (default: number of CPUs)",
+    },
+    // no -r: grep and rg use it for recursive search
+    Opt {
+        short: None,
+        long: "replace",
+        value: Some("TEXT"),
+        help: "show a diff replacing each match with TEXT ($1, ${name} refer to groups with -E)",
+    },
+    Opt {
+        short: None,
+        long: "write",
+        value: None,
+        help: "with --replace, rewrite the files instead of showing the diff",
+    },
+    Opt {
+        short: Some('g'),
+        long: "glob",
+        value: Some("GLOB"),
+        help: "only search files matching GLOB, i.e. '*.rs' (can be repeated, overrides ignore files)",
+    },
+    Opt {
+        short: None,
+        long: "exclude",
+        value: Some("GLOB"),
+        help: "never search files or directories matching GLOB, i.e. 'target/**' (can be repeated)",
+    },
+    Opt {
+        short: None,
+        long: "no-ignore",
+        value: None,
+        help: "don't skip files listed in .gitignore/.ignore files",
+    },
+    Opt {
+        short: None,
+        long: "no-index",
+        value: None,
+        help: "search every file, even in a directory with an index (see `cli-proj index`)",
+    },
+    Opt {
+        short: None,
+        long: "follow",
+        value: None,
+        help: "keep searching the files as they grow (and directories for new files) until interrupted",
+    },
+    Opt {
+        short: None,
+        long: "watch",
+        value: None,
+        help: "the same as --follow",
+    },
+    // no -h: grep and rg use it for --no-filename
+    Opt {
+        short: None,
+        long: "help",
+        value: None,
+        help: "print this help and exit",
+    },
+    Opt {
+        short: Some('V'),
+        long: "version",
+        value: None,
+        help: "print the version and exit",
+    },
+];
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    MissingQuery,
+    MissingPath,
+    UnknownOption(String),
+    // the option as it was written, i.e. "-A" or "--after-context"
+    MissingValue(String),
+    UnexpectedValue(String),
+    ConflictingOptions(String, String),
+    // the first option only makes sense together with the second
+    Requires(String, String),
+    InvalidNumber {
+        option: String,
+        value: String,
+    },
+    InvalidChoice {
+        option: String,
+        value: String,
+        choices: &'static str,
+    },
+    InvalidRegex(regex::Error),
+    InvalidGlob(String),
+    // -f FILE couldn't be read, with the reason why
+    PatternFile {
+        path: String,
+        error: String,
+    },
+    // not really errors, but they stop parsing the same way, main prints the text and exits successfully
+    HelpRequested,
+    VersionRequested,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::MissingQuery => write!(f, "no query provided!"),
+            ParseError::MissingPath => write!(f, "no filename provided!"),
+            ParseError::UnknownOption(option) => write!(f, "unknown option {}", option),
+            ParseError::MissingValue(option) => write!(f, "{} needs a value", option),
+            ParseError::UnexpectedValue(option) => write!(f, "{} doesn't take a value", option),
+            ParseError::ConflictingOptions(a, b) => write!(f, "{} can't be used with {}", a, b),
+            ParseError::Requires(a, b) => write!(f, "{} requires {}", a, b),
+            ParseError::InvalidNumber { option, value } => {
+                write!(f, "{} expects a number, got {:?}", option, value)
+            }
+            ParseError::InvalidChoice {
+                option,
+                value,
+                choices,
+            } => {
+                write!(f, "{} must be one of {}, got {:?}", option, choices, value)
+            }
+            ParseError::InvalidRegex(e) => write!(f, "invalid regular expression: {}", e),
+            ParseError::InvalidGlob(glob) => write!(f, "invalid glob {:?}", glob),
+            ParseError::PatternFile { path, error } => {
+                write!(f, "can't read patterns from {}: {}", path, error)
+            }
+            ParseError::HelpRequested => write!(f, "{}", usage().trim_end()),
+            ParseError::VersionRequested => write!(f, "cli-proj {}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::InvalidRegex(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Parsed {
+    // in the order they were given, so a later option can override an earlier one
+    pub options: Vec<(&'static Opt, Option<String>)>,
+    pub positional: Vec<String>,
+}
+
+// Understands the usual unix conventions:
+//   --long, --long value, --long=value
+//   -s, -s value, -svalue, and several flags squashed together like -iE or -iC2
+//   -- ends the options, everything after it is positional (i.e. to search for "-i" literally)
+//   - on its own is positional (it means stdin)
+pub fn parse(args: impl Iterator<Item = String>) -> Result<Parsed, ParseError> {
+    let mut parsed = Parsed::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            parsed.positional.extend(args.by_ref());
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let opt = OPTIONS
+                .iter()
+                .find(|opt| opt.long == name)
+                .ok_or_else(|| ParseError::UnknownOption(format!("--{}", name)))?;
+            let value = match (opt.value, inline) {
+                (Some(_), Some(value)) => Some(value),
+                (Some(_), None) => Some(args.next().ok_or(ParseError::MissingValue(arg.clone()))?),
+                (None, Some(_)) => return Err(ParseError::UnexpectedValue(format!("--{}", name))),
+                (None, None) => None,
+            };
+            parsed.options.push((opt, value));
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            // a cluster of short flags, the first one that takes a value eats the rest of the cluster (or the next arg)
+            let cluster = &arg[1..];
+            for (i, c) in cluster.char_indices() {
+                let opt = OPTIONS
+                    .iter()
+                    .find(|opt| opt.short == Some(c))
+                    .ok_or_else(|| ParseError::UnknownOption(format!("-{}", c)))?;
+                if opt.value.is_none() {
+                    parsed.options.push((opt, None));
+                    continue;
+                }
+                let rest = &cluster[i + c.len_utf8()..];
+                let value = if rest.is_empty() {
+                    args.next()
+                        .ok_or_else(|| ParseError::MissingValue(format!("-{}", c)))?
+                } else {
+                    rest.to_string()
+                };
+                parsed.options.push((opt, Some(value)));
+                break;
+            }
+        } else {
+            parsed.positional.push(arg);
+        }
+    }
+    Ok(parsed)
+}
+
+pub fn usage() -> String {
+    let flags: Vec<String> = OPTIONS
+        .iter()
+        .map(|opt| {
+            let short = opt
+                .short
+                .map_or("    ".to_string(), |c| format!("-{}, ", c));
+            let value = opt.value.map_or(String::new(), |v| format!(" <{}>", v));
+            format!("{}--{}{}", short, opt.long, value)
+        })
+        .collect();
+    let width = flags.iter().map(String::len).max().unwrap_or(0);
+
+    let mut text = String::from(
+        "Usage: cli-proj [OPTIONS] <QUERY> <PATH>...\n\
+         \x20      cli-proj [OPTIONS] (-e <PATTERN> | -f <FILE>)... <PATH>...\n\
+         \x20      cli-proj index <DIR>...\n\n\
+         Search for QUERY in each PATH: a file, a directory (searched recursively) or - for stdin.\n\
+         With -e or -f there is no QUERY, lines matching any of the patterns are selected.\n\
+         Gzip files (told apart by their contents, not their name) are searched as the text inside them.\n\
+         Files don't have to be UTF-8. In a regex \\xe9 is the char é; write (?-u:\\xe9) for the byte 0xe9.\n\
+         `cli-proj index DIR` builds (or updates) a trigram index in DIR/.cli-proj-index, which later searches\n\
+         of DIR use to skip files that can't match. To search for the word \"index\" itself, use -e index.\n\n\
+         Options:\n",
+    );
+    for (flag, opt) in flags.iter().zip(OPTIONS) {
+        text.push_str(&format!("  {:width$}  {}\n", flag, opt.help, width = width));
+    }
+    text.push_str(
+        "\nExit status is 0 if a line (or with -L a file) was selected, 1 if none was, 2 on error.\n",
+    );
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_strs(args: &[&str]) -> Result<Parsed, ParseError> {
+        parse(args.iter().map(|s| s.to_string()))
+    }
+
+    fn longs(parsed: &Parsed) -> Vec<(&str, Option<&str>)> {
+        parsed
+            .options
+            .iter()
+            .map(|(opt, value)| (opt.long, value.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn short_and_long_options() {
+        let parsed = parse_strs(&["-i", "--after-context", "2", "--context=3", "q", "f"]).unwrap();
+
+        assert_eq!(
+            vec![
+                ("ignore-case", None),
+                ("after-context", Some("2")),
+                ("context", Some("3"))
+            ],
+            longs(&parsed)
+        );
+        assert_eq!(vec!["q", "f"], parsed.positional);
+    }
+
+    #[test]
+    fn combined_short_flags() {
+        let parsed = parse_strs(&["-iEC2", "-A", "1", "q", "f"]).unwrap();
+
+        assert_eq!(
+            vec![
+                ("ignore-case", None),
+                ("regex", None),
+                ("context", Some("2")),
+                ("after-context", Some("1"))
+            ],
+            longs(&parsed)
+        );
+    }
+
+    #[test]
+    fn double_dash_ends_options() {
+        let parsed = parse_strs(&["-i", "--", "-E", "-"]).unwrap();
+
+        assert_eq!(vec![("ignore-case", None)], longs(&parsed));
+        assert_eq!(vec!["-E", "-"], parsed.positional);
+    }
+
+    #[test]
+    fn structured_errors() {
+        assert_eq!(
+            ParseError::UnknownOption("-z".to_string()),
+            parse_strs(&["-iz"]).unwrap_err()
+        );
+        assert_eq!(
+            ParseError::UnknownOption("--nope".to_string()),
+            parse_strs(&["--nope"]).unwrap_err()
+        );
+        assert_eq!(
+            ParseError::MissingValue("--threads".to_string()),
+            parse_strs(&["--threads"]).unwrap_err()
+        );
+        assert_eq!(
+            ParseError::MissingValue("-A".to_string()),
+            parse_strs(&["q", "-A"]).unwrap_err()
+        );
+        assert_eq!(
+            ParseError::UnexpectedValue("--regex".to_string()),
+            parse_strs(&["--regex=yes"]).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn no_short_flags_that_mean_something_else_in_grep() {
+        for flag in ["-r", "-h"] {
+            assert_eq!(
+                ParseError::UnknownOption(flag.to_string()),
+                parse_strs(&[flag]).unwrap_err()
+            );
+        }
+    }
+
+    #[test]
+    fn usage_lists_every_option() {
+        let text = usage();
+
+        for opt in OPTIONS {
+            assert!(text.contains(&format!("--{}", opt.long)));
+            assert!(text.contains(opt.help));
+        }
+        assert!(text.contains("  -A, --after-context <NUM>   print NUM lines after each match\n"));
+        assert!(text.contains("      --column"));
+    }
+}
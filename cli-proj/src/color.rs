@@ -0,0 +1,94 @@
+// ANSI colouring for terminal output, in the same spirit (and roughly the same colours) as grep --color:
+// matches in bold red, file names in magenta, line/column numbers in green and separators in cyan.
+use std::env;
+use std::io::{self, IsTerminal};
+
+pub const MATCH: &str = "\x1b[1;31m";
+pub const PATH: &str = "\x1b[35m";
+pub const LINE_NUMBER: &str = "\x1b[32m";
+pub const SEPARATOR: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    // colour only when stdout is a terminal, and NO_COLOR isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(value: &str) -> Option<ColorChoice> {
+        match value {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    // Decides once, up front, whether this run prints colour.
+    pub fn resolve(self) -> bool {
+        should_color(
+            self,
+            io::stdout().is_terminal(),
+            env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()),
+        )
+    }
+}
+
+// Split out from resolve so the rules can be tested without a terminal or touching the environment.
+// NO_COLOR (https://no-color.org) only changes the default, an explicit --color=always still wins.
+pub fn should_color(choice: ColorChoice, is_terminal: bool, no_color: bool) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => is_terminal && !no_color,
+    }
+}
+
+// wraps text in a colour and a reset, so nothing leaks into the rest of the line
+pub fn paint(style: &str, text: &str) -> String {
+    format!("{}{}{}", style, text, RESET)
+}
+
+// The line with each of the byte ranges in `spans` painted as a match, decoded for the terminal
+// the same way as an uncoloured line (invalid UTF-8 shows up as U+FFFD).
+pub fn highlight(line: &[u8], spans: &[std::ops::Range<usize>]) -> String {
+    let mut out = String::with_capacity(line.len() + spans.len() * 16);
+    let mut last = 0;
+    for span in spans {
+        out.push_str(&String::from_utf8_lossy(&line[last..span.start]));
+        out.push_str(&paint(MATCH, &String::from_utf8_lossy(&line[span.clone()])));
+        last = span.end;
+    }
+    out.push_str(&String::from_utf8_lossy(&line[last..]));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_follows_terminal_and_no_color() {
+        assert!(should_color(ColorChoice::Auto, true, false));
+        assert!(!should_color(ColorChoice::Auto, false, false));
+        assert!(!should_color(ColorChoice::Auto, true, true));
+    }
+
+    #[test]
+    fn explicit_choice_wins() {
+        assert!(should_color(ColorChoice::Always, false, true));
+        assert!(!should_color(ColorChoice::Never, true, false));
+    }
+
+    #[test]
+    fn highlights_each_span() {
+        assert_eq!(
+            "a \x1b[1;31mab\x1b[0m c \x1b[1;31mab\x1b[0m",
+            highlight(b"a ab c ab", &[2..4, 7..9])
+        );
+        assert_eq!("plain", highlight(b"plain", &[]));
+    }
+}
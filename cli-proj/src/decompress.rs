@@ -0,0 +1,74 @@
+// Searching compressed files (rotated logs like app.log.1.gz) as if they were the text inside them.
+// The decoder sits between the file and the line-by-line search in stream.rs, so a compressed file is
+// never unpacked to disk or into memory as a whole, and its matches look exactly like the plain file's.
+use std::io::{self, BufRead, BufReader};
+
+use flate2::bufread::MultiGzDecoder;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+impl Compression {
+    // A file is compressed if it starts with the format's magic bytes, whatever it's called (so a
+    // renamed file, or one piped into stdin, is still found). A name ending in .gz isn't enough: a plain
+    // text x.gz is searched as the text it is. `head` is the start of the file.
+    pub fn detect(head: &[u8]) -> Option<Compression> {
+        head.starts_with(&GZIP_MAGIC).then_some(Compression::Gzip)
+    }
+
+    // Wraps the raw reader in the decoder. MultiGzDecoder carries on through every member of the file,
+    // since logs are often appended to as separate gzip streams (cat a.gz b.gz > c.gz is a valid gzip file).
+    pub fn decoder<'a, R: BufRead + 'a>(self, reader: R) -> Box<dyn BufRead + 'a> {
+        match self {
+            Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        }
+    }
+}
+
+// The file as a BufRead of its (decompressed, if need be) contents.
+pub fn reader<'a, R: BufRead + 'a>(mut reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    let head = reader.fill_buf()?;
+    Ok(match Compression::detect(head) {
+        Some(compression) => compression.decoder(reader),
+        None => Box::new(reader),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::{Read, Write};
+
+    fn gzip(text: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn detects_by_magic() {
+        assert_eq!(Some(Compression::Gzip), Compression::detect(&gzip("hello")));
+        assert_eq!(None, Compression::detect(b"hello"));
+    }
+
+    fn read(bytes: &[u8]) -> String {
+        let mut text = String::new();
+        reader(bytes).unwrap().read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn reads_through_every_member() {
+        let mut both = gzip("one\n");
+        both.extend(gzip("two\n"));
+
+        assert_eq!("one\ntwo\n", read(&both));
+        // plain files come through untouched, even when they're called x.gz
+        assert_eq!("plain", read(b"plain"));
+    }
+}
@@ -0,0 +1,360 @@
+// Decides which files found while walking a directory get searched: the rules from .gitignore/.ignore files,
+// plus --glob (only search matching files) and --exclude (never search matching files or directories).
+//
+// Precedence follows git and ripgrep:
+//   1. --exclude always wins, an excluded directory isn't even walked
+//   2. if any --glob was given, a file is searched exactly when it matches one, whatever the ignore files say
+//   3. otherwise ignore files decide: a file in a deeper directory overrides one further up, within a
+//      directory .ignore overrides .gitignore, and within a file the last matching line wins (so `!keep.log`
+//      after `*.log` re-includes it). As in git, nothing inside an ignored directory can be re-included,
+//      because the directory is never walked.
+//
+// Paths named on the command line are never filtered, only what's discovered while walking.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+// One glob, compiled into a regex over a '/'-separated path relative to where it applies.
+// A glob without a '/' matches a name at any depth (`*.rs`), one with a '/' is anchored there (`src/*.rs`).
+#[derive(Debug, Clone)]
+pub struct Glob {
+    regex: Regex,
+}
+
+impl Glob {
+    pub fn new(glob: &str) -> Result<Glob, regex::Error> {
+        let anchored = glob.contains('/');
+        let glob = glob.strip_prefix('/').unwrap_or(glob);
+        let mut pattern = String::from("^");
+        if !anchored {
+            pattern.push_str("(?:.*/)?");
+        }
+        pattern.push_str(&glob_to_regex(glob));
+        pattern.push('$');
+        Ok(Glob {
+            regex: Regex::new(&pattern)?,
+        })
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        self.regex.is_match(path)
+    }
+}
+
+// *  any run of characters except '/'
+// ** any run of directories, when it's a whole path segment (`**/x`, `a/**/b`, `a/**`)
+// ?  any one character except '/'
+// [abc], [a-z], [!abc]  a character class, negated with ! (or ^)
+// \x the character x, literally
+fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let starts_segment = i == 0 || chars[i - 1] == '/';
+                match chars.get(i + 2) {
+                    Some('/') if starts_segment => {
+                        out.push_str("(?:.*/)?");
+                        i += 3;
+                    }
+                    None if starts_segment => {
+                        out.push_str(".*");
+                        i += 2;
+                    }
+                    // `a**b` isn't special, it's just two single stars
+                    _ => {
+                        out.push_str("[^/]*[^/]*");
+                        i += 2;
+                    }
+                }
+                continue;
+            }
+            '*' => out.push_str("[^/]*"),
+            '?' => out.push_str("[^/]"),
+            '[' => match chars[i + 1..].iter().position(|&c| c == ']') {
+                Some(len) if len > 0 => {
+                    let class: String = chars[i + 1..i + 1 + len].iter().collect();
+                    let (negated, class) = match class.strip_prefix(['!', '^']) {
+                        Some(rest) => (true, rest.to_string()),
+                        None => (false, class),
+                    };
+                    out.push('[');
+                    if negated {
+                        out.push('^');
+                    }
+                    for c in class.chars() {
+                        // keep ranges working, escape anything else the regex syntax might read specially
+                        if c == '-' {
+                            out.push('-');
+                        } else {
+                            out.push_str(&regex::escape(&c.to_string()));
+                        }
+                    }
+                    out.push(']');
+                    i += len + 2;
+                    continue;
+                }
+                // no closing bracket, so it's just a '['
+                _ => out.push_str(r"\["),
+            },
+            '\\' if i + 1 < chars.len() => {
+                out.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 2;
+                continue;
+            }
+            c => out.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    out
+}
+
+// A line of a .gitignore/.ignore file.
+#[derive(Debug)]
+struct Rule {
+    glob: Glob,
+    // `!pattern` re-includes what an earlier rule ignored
+    negated: bool,
+    // `pattern/` only matches directories
+    dir_only: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        // \# and \! at the start are how a pattern begins with those characters literally
+        let line = line
+            .strip_prefix('\\')
+            .filter(|rest| rest.starts_with(['#', '!']))
+            .unwrap_or(line);
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        // a broken pattern in somebody's ignore file just doesn't match, rather than stopping the search
+        let glob = Glob::new(line).ok()?;
+        Some(Rule {
+            glob,
+            negated,
+            dir_only,
+        })
+    }
+}
+
+// The rules from the ignore files of one directory, which apply to paths below it.
+#[derive(Debug)]
+pub struct IgnoreFile {
+    // the directory, relative to the root being walked
+    base: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl IgnoreFile {
+    // Reads `dir`'s .gitignore then .ignore, so .ignore's lines come last and take precedence.
+    pub fn load(dir: &Path, base: &Path) -> io::Result<Option<IgnoreFile>> {
+        let mut rules = Vec::new();
+        for name in [".gitignore", ".ignore"] {
+            match fs::read_to_string(dir.join(name)) {
+                Ok(contents) => rules.extend(contents.lines().filter_map(Rule::parse)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if rules.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(IgnoreFile {
+            base: base.to_path_buf(),
+            rules,
+        }))
+    }
+
+    // Some(true) if ignored, Some(false) if re-included by a `!` rule, None if no rule mentions the path.
+    fn decide(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.base).ok()?;
+        let relative = relative.to_string_lossy();
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.glob.is_match(&relative))
+            .map(|rule| !rule.negated)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Filter {
+    globs: Vec<Glob>,
+    excludes: Vec<Glob>,
+    respect_ignore: bool,
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter {
+            globs: Vec::new(),
+            excludes: Vec::new(),
+            respect_ignore: true,
+        }
+    }
+}
+
+impl Filter {
+    // On a bad glob, the error is the glob that failed to compile.
+    pub fn new(
+        globs: &[String],
+        excludes: &[String],
+        respect_ignore: bool,
+    ) -> Result<Filter, String> {
+        let compile = |globs: &[String]| {
+            globs
+                .iter()
+                .map(|g| Glob::new(g).map_err(|_| g.clone()))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Filter {
+            globs: compile(globs)?,
+            excludes: compile(excludes)?,
+            respect_ignore,
+        })
+    }
+
+    pub fn respects_ignore_files(&self) -> bool {
+        self.respect_ignore
+    }
+
+    // `path` is relative to the root being walked, `ignores` are the ignore files from the root down to its parent.
+    pub fn allows(&self, path: &Path, is_dir: bool, ignores: &[IgnoreFile]) -> bool {
+        let relative = path.to_string_lossy();
+        // for a directory, also try it with a trailing slash so `target/**` prunes target itself
+        let excluded = self.excludes.iter().any(|glob| {
+            glob.is_match(&relative) || (is_dir && glob.is_match(&format!("{}/", relative)))
+        });
+        if excluded {
+            return false;
+        }
+        // --glob picks files, it never stops a directory from being walked
+        if !is_dir && !self.globs.is_empty() {
+            return self.globs.iter().any(|glob| glob.is_match(&relative));
+        }
+        if !self.respect_ignore {
+            return true;
+        }
+        // the deepest ignore file with an opinion wins
+        let ignored = ignores
+            .iter()
+            .rev()
+            .find_map(|ignore| ignore.decide(path, is_dir))
+            .unwrap_or(false);
+        !ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(g: &str) -> Glob {
+        Glob::new(g).unwrap()
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob("*.rs").is_match("lib.rs"));
+        assert!(glob("*.rs").is_match("src/deep/lib.rs"));
+        assert!(!glob("*.rs").is_match("lib.rs.bak"));
+        assert!(glob("src/*.rs").is_match("src/lib.rs"));
+        assert!(!glob("src/*.rs").is_match("src/deep/lib.rs"));
+        assert!(!glob("src/*.rs").is_match("other/src/lib.rs"));
+        assert!(glob("src/**/*.rs").is_match("src/lib.rs"));
+        assert!(glob("src/**/*.rs").is_match("src/a/b/lib.rs"));
+        assert!(glob("target/**").is_match("target/debug/cli-proj"));
+        assert!(!glob("target/**").is_match("target"));
+        assert!(glob("**/test").is_match("a/b/test"));
+        assert!(glob("file?.txt").is_match("file1.txt"));
+        assert!(glob("[a-c]*.txt").is_match("b.txt"));
+        assert!(!glob("[!a-c]*.txt").is_match("b.txt"));
+        assert!(glob(r"\*.txt").is_match("*.txt"));
+        assert!(!glob(r"\*.txt").is_match("a.txt"));
+        assert!(glob("a+b (1).txt").is_match("a+b (1).txt"));
+    }
+
+    fn ignore_file(base: &str, contents: &str) -> IgnoreFile {
+        IgnoreFile {
+            base: PathBuf::from(base),
+            rules: contents.lines().filter_map(Rule::parse).collect(),
+        }
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let ignores = [ignore_file("", "# logs\n*.log\n!keep.log\n")];
+        let filter = Filter::default();
+
+        assert!(!filter.allows(Path::new("debug.log"), false, &ignores));
+        assert!(filter.allows(Path::new("keep.log"), false, &ignores));
+        assert!(filter.allows(Path::new("main.rs"), false, &ignores));
+    }
+
+    #[test]
+    fn deeper_ignore_file_wins() {
+        let ignores = [ignore_file("", "*.log\n"), ignore_file("logs", "!*.log\n")];
+        let filter = Filter::default();
+
+        assert!(!filter.allows(Path::new("debug.log"), false, &ignores));
+        assert!(filter.allows(Path::new("logs/debug.log"), false, &ignores));
+    }
+
+    #[test]
+    fn anchored_and_directory_rules() {
+        let ignores = [ignore_file("", "/target\nbuild/\n")];
+        let filter = Filter::default();
+
+        assert!(!filter.allows(Path::new("target"), true, &ignores));
+        // a leading slash anchors the rule to the directory of the ignore file
+        assert!(filter.allows(Path::new("src/target"), true, &ignores));
+        assert!(!filter.allows(Path::new("src/build"), true, &ignores));
+        // a trailing slash only matches directories
+        assert!(filter.allows(Path::new("build"), false, &ignores));
+    }
+
+    #[test]
+    fn globs_override_ignore_files_and_excludes_override_globs() {
+        let ignores = [ignore_file("", "generated.rs\n")];
+        let filter = Filter::new(&["*.rs".to_string()], &["vendor/**".to_string()], true).unwrap();
+
+        assert!(filter.allows(Path::new("generated.rs"), false, &ignores));
+        assert!(!filter.allows(Path::new("notes.txt"), false, &ignores));
+        assert!(!filter.allows(Path::new("vendor/lib.rs"), false, &ignores));
+        assert!(!filter.allows(Path::new("vendor"), true, &ignores));
+        // --glob never stops a directory from being walked
+        assert!(filter.allows(Path::new("docs"), true, &ignores));
+    }
+
+    #[test]
+    fn no_ignore() {
+        let ignores = [ignore_file("", "*.log\n")];
+        let filter = Filter::new(&[], &[], false).unwrap();
+
+        assert!(filter.allows(Path::new("debug.log"), false, &ignores));
+    }
+
+    #[test]
+    fn escaped_rules() {
+        let ignores = [ignore_file("", "\\#notes\n\\!important\n")];
+        let filter = Filter::default();
+
+        assert!(!filter.allows(Path::new("#notes"), false, &ignores));
+        assert!(!filter.allows(Path::new("!important"), false, &ignores));
+    }
+}
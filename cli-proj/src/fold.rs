@@ -0,0 +1,178 @@
+// Case-insensitive substring search using Unicode case folding.
+// Lowercasing both sides (the old approach) allocates a new String for every line, and lowercase
+// isn't the same as "ignoring case": "STRASSE" and "straße" are the same word, but no amount of
+// lowercasing makes them equal. Case folding maps every char to a form where those compare equal.
+//
+// The query is folded once up front; each line is folded one char at a time while it's compared,
+// so searching never allocates. Matches start and end on char boundaries of the original line (an
+// invalid byte is a U+FFFD of its own, see utf8.rs).
+use std::ops::Range;
+use std::{char, str};
+
+use crate::fold_table::FOLDING;
+use crate::utf8;
+
+pub struct FoldedQuery {
+    folded: Vec<char>,
+}
+
+impl FoldedQuery {
+    pub fn new(query: &str) -> FoldedQuery {
+        FoldedQuery {
+            folded: query.chars().flat_map(fold).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.folded.is_empty()
+    }
+
+    // the first hit starting at or after byte `from` of the line
+    pub fn find_from(&self, line: &[u8], from: usize) -> Option<Range<usize>> {
+        utf8::char_indices(&line[from..]).find_map(|(at, _)| {
+            self.match_at(line, from + at.start)
+                .map(|end| from + at.start..end)
+        })
+    }
+
+    // If the query matches the line starting at byte `start`, returns where the match ends.
+    fn match_at(&self, line: &[u8], start: usize) -> Option<usize> {
+        let mut q = 0;
+        for (at, c) in utf8::char_indices(&line[start..]) {
+            for f in fold(c) {
+                // a match has to cover whole chars, so running out of query halfway through
+                // one char's folding (i.e. "s" against "ß", which folds to "ss") isn't a match
+                if self.folded.get(q) != Some(&f) {
+                    return None;
+                }
+                q += 1;
+            }
+            if q == self.folded.len() {
+                return Some(start + at.end);
+            }
+        }
+        None
+    }
+}
+
+// The chars a single char folds to. char::to_lowercase already does the right thing for most chars
+// (including 'İ' → "i̇", which is also its full case folding); fold_table.rs lists the ones where Unicode's
+// CaseFolding.txt says otherwise: "ß" → "ss", "µ" → "μ", "ϐ" → "β", Greek letters with an iota subscript,
+// ligatures, Cherokee (which folds to uppercase) and more. Turkish/Azeri dotless i rules are locale
+// specific, and like the regex crate we don't apply them.
+fn fold(c: char) -> Folded {
+    match FOLDING.binary_search_by_key(&c, |&(from, _)| from) {
+        Ok(i) => Folded::Special(FOLDING[i].1.chars()),
+        Err(_) => Folded::Lower(c.to_lowercase()),
+    }
+}
+
+// The chars c is compared as: itself when case matters, its folding when it doesn't.
+pub(crate) fn fold_if(c: char, fold_case: bool) -> Folded {
+    if fold_case {
+        fold(c)
+    } else {
+        Folded::Same(Some(c))
+    }
+}
+
+// to_lowercase and a special-case string are different iterator types, this lets fold return either
+pub(crate) enum Folded {
+    Lower(char::ToLowercase),
+    Special(str::Chars<'static>),
+    Same(Option<char>),
+}
+
+impl Iterator for Folded {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        match self {
+            Folded::Lower(chars) => chars.next(),
+            Folded::Special(chars) => chars.next(),
+            Folded::Same(c) => c.take(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(query: &str, line: &str) -> Option<Range<usize>> {
+        FoldedQuery::new(query).find_from(line.as_bytes(), 0)
+    }
+
+    #[test]
+    fn ascii() {
+        assert_eq!(Some(0..4), find("RUST", "Rust:"));
+        assert_eq!(Some(1..5), find("rust", "trust me."));
+        assert_eq!(None, find("rust", "rus"));
+    }
+
+    #[test]
+    fn sharp_s_folds_to_ss() {
+        let line = "Die Straße ist lang";
+
+        assert_eq!(Some(4..11), find("STRASSE", line));
+        assert_eq!("Straße", &line[4..11]);
+        assert_eq!(Some(0..7), find("straße", "STRASSE"));
+        // half of a ß isn't a match
+        assert_eq!(None, find("s", "ß"));
+    }
+
+    #[test]
+    fn cyrillic() {
+        // the same greeting the collections crate slices up
+        let line = "Здравствуйте, мир";
+
+        assert_eq!(Some(0..24), find("ЗДРАВСТВУЙТЕ", line));
+        assert_eq!(Some(0..24), find("здравствуйте", line));
+        assert_eq!(Some(26..32), find("МИР", line));
+    }
+
+    #[test]
+    fn turkish_dotted_capital_i() {
+        // 'İ' folds to "i̇" (i and a combining dot above), so it matches that and not a plain "i"
+        assert_eq!(Some(0..10), find("İstanbul", "i\u{307}stanbul"));
+        assert_eq!(None, find("İstanbul", "istanbul"));
+        // and no locale specific rules, a dotless ı is its own letter
+        assert_eq!(None, find("I", "ı"));
+    }
+
+    #[test]
+    fn greek_final_sigma() {
+        assert_eq!(Some(0..10), find("ΣΟΦΟΣ", "σοφος"));
+        assert_eq!(Some(0..10), find("σοφοσ", "ΣΟΦΟς"));
+    }
+
+    #[test]
+    fn folds_that_lowercasing_misses() {
+        // the micro sign is a Greek mu
+        assert_eq!(Some(0..2), find("µ", "Μ"));
+        assert_eq!(Some(0..2), find("µ", "μ"));
+        assert_eq!(Some(0..2), find("Μ", "µ"));
+        // and the curly beta is a beta
+        assert_eq!(Some(0..2), find("ϐ", "Β"));
+        assert_eq!(Some(0..2), find("β", "ϐ"));
+        // ᾳ (alpha with ypogegrammeni) is an alpha and an iota
+        assert_eq!(Some(0..3), find("ΑΙ", "ᾳ"));
+        // Cherokee small letters fold to the capitals, not the other way round
+        assert_eq!(Some(0..3), find("Ꭰ", "ꭰ"));
+        assert_eq!(Some(0..3), find("ꭰ", "Ꭰ"));
+    }
+
+    #[test]
+    fn table_is_sorted() {
+        assert!(FOLDING.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn match_offsets_are_in_the_original_line() {
+        // 'İ' is two bytes in the line but three once folded, the match still points at the right bytes
+        let line = "İ RUST";
+
+        assert_eq!(Some(3..7), find("rust", line));
+        assert_eq!(Some(0..2), find("i\u{307}", line));
+    }
+}
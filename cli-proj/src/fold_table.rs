@@ -0,0 +1,307 @@
+// Generated: every char whose full case folding (the C and F entries of Unicode's CaseFolding.txt,
+// without the Turkic T entries) isn't what char::to_lowercase gives. fold.rs looks chars up here
+// before falling back to to_lowercase. Sorted by char, for binary search.
+//
+// To regenerate, with Python (whose str.casefold is exactly that folding, and whose str.lower agrees
+// with to_lowercase on every char its Unicode data knows about):
+//   for c in map(chr, range(0x110000)):
+//       if c.casefold() != c.lower(): print(c, c.casefold())
+pub(crate) const FOLDING: &[(char, &str)] = &[
+    ('\u{00b5}', "\u{03bc}"),
+    ('\u{00df}', "ss"),
+    ('\u{0149}', "\u{02bc}n"),
+    ('\u{017f}', "s"),
+    ('\u{01f0}', "j\u{030c}"),
+    ('\u{0345}', "\u{03b9}"),
+    ('\u{0390}', "\u{03b9}\u{0308}\u{0301}"),
+    ('\u{03b0}', "\u{03c5}\u{0308}\u{0301}"),
+    ('\u{03c2}', "\u{03c3}"),
+    ('\u{03d0}', "\u{03b2}"),
+    ('\u{03d1}', "\u{03b8}"),
+    ('\u{03d5}', "\u{03c6}"),
+    ('\u{03d6}', "\u{03c0}"),
+    ('\u{03f0}', "\u{03ba}"),
+    ('\u{03f1}', "\u{03c1}"),
+    ('\u{03f5}', "\u{03b5}"),
+    ('\u{0587}', "\u{0565}\u{0582}"),
+    ('\u{13a0}', "\u{13a0}"),
+    ('\u{13a1}', "\u{13a1}"),
+    ('\u{13a2}', "\u{13a2}"),
+    ('\u{13a3}', "\u{13a3}"),
+    ('\u{13a4}', "\u{13a4}"),
+    ('\u{13a5}', "\u{13a5}"),
+    ('\u{13a6}', "\u{13a6}"),
+    ('\u{13a7}', "\u{13a7}"),
+    ('\u{13a8}', "\u{13a8}"),
+    ('\u{13a9}', "\u{13a9}"),
+    ('\u{13aa}', "\u{13aa}"),
+    ('\u{13ab}', "\u{13ab}"),
+    ('\u{13ac}', "\u{13ac}"),
+    ('\u{13ad}', "\u{13ad}"),
+    ('\u{13ae}', "\u{13ae}"),
+    ('\u{13af}', "\u{13af}"),
+    ('\u{13b0}', "\u{13b0}"),
+    ('\u{13b1}', "\u{13b1}"),
+    ('\u{13b2}', "\u{13b2}"),
+    ('\u{13b3}', "\u{13b3}"),
+    ('\u{13b4}', "\u{13b4}"),
+    ('\u{13b5}', "\u{13b5}"),
+    ('\u{13b6}', "\u{13b6}"),
+    ('\u{13b7}', "\u{13b7}"),
+    ('\u{13b8}', "\u{13b8}"),
+    ('\u{13b9}', "\u{13b9}"),
+    ('\u{13ba}', "\u{13ba}"),
+    ('\u{13bb}', "\u{13bb}"),
+    ('\u{13bc}', "\u{13bc}"),
+    ('\u{13bd}', "\u{13bd}"),
+    ('\u{13be}', "\u{13be}"),
+    ('\u{13bf}', "\u{13bf}"),
+    ('\u{13c0}', "\u{13c0}"),
+    ('\u{13c1}', "\u{13c1}"),
+    ('\u{13c2}', "\u{13c2}"),
+    ('\u{13c3}', "\u{13c3}"),
+    ('\u{13c4}', "\u{13c4}"),
+    ('\u{13c5}', "\u{13c5}"),
+    ('\u{13c6}', "\u{13c6}"),
+    ('\u{13c7}', "\u{13c7}"),
+    ('\u{13c8}', "\u{13c8}"),
+    ('\u{13c9}', "\u{13c9}"),
+    ('\u{13ca}', "\u{13ca}"),
+    ('\u{13cb}', "\u{13cb}"),
+    ('\u{13cc}', "\u{13cc}"),
+    ('\u{13cd}', "\u{13cd}"),
+    ('\u{13ce}', "\u{13ce}"),
+    ('\u{13cf}', "\u{13cf}"),
+    ('\u{13d0}', "\u{13d0}"),
+    ('\u{13d1}', "\u{13d1}"),
+    ('\u{13d2}', "\u{13d2}"),
+    ('\u{13d3}', "\u{13d3}"),
+    ('\u{13d4}', "\u{13d4}"),
+    ('\u{13d5}', "\u{13d5}"),
+    ('\u{13d6}', "\u{13d6}"),
+    ('\u{13d7}', "\u{13d7}"),
+    ('\u{13d8}', "\u{13d8}"),
+    ('\u{13d9}', "\u{13d9}"),
+    ('\u{13da}', "\u{13da}"),
+    ('\u{13db}', "\u{13db}"),
+    ('\u{13dc}', "\u{13dc}"),
+    ('\u{13dd}', "\u{13dd}"),
+    ('\u{13de}', "\u{13de}"),
+    ('\u{13df}', "\u{13df}"),
+    ('\u{13e0}', "\u{13e0}"),
+    ('\u{13e1}', "\u{13e1}"),
+    ('\u{13e2}', "\u{13e2}"),
+    ('\u{13e3}', "\u{13e3}"),
+    ('\u{13e4}', "\u{13e4}"),
+    ('\u{13e5}', "\u{13e5}"),
+    ('\u{13e6}', "\u{13e6}"),
+    ('\u{13e7}', "\u{13e7}"),
+    ('\u{13e8}', "\u{13e8}"),
+    ('\u{13e9}', "\u{13e9}"),
+    ('\u{13ea}', "\u{13ea}"),
+    ('\u{13eb}', "\u{13eb}"),
+    ('\u{13ec}', "\u{13ec}"),
+    ('\u{13ed}', "\u{13ed}"),
+    ('\u{13ee}', "\u{13ee}"),
+    ('\u{13ef}', "\u{13ef}"),
+    ('\u{13f0}', "\u{13f0}"),
+    ('\u{13f1}', "\u{13f1}"),
+    ('\u{13f2}', "\u{13f2}"),
+    ('\u{13f3}', "\u{13f3}"),
+    ('\u{13f4}', "\u{13f4}"),
+    ('\u{13f5}', "\u{13f5}"),
+    ('\u{13f8}', "\u{13f0}"),
+    ('\u{13f9}', "\u{13f1}"),
+    ('\u{13fa}', "\u{13f2}"),
+    ('\u{13fb}', "\u{13f3}"),
+    ('\u{13fc}', "\u{13f4}"),
+    ('\u{13fd}', "\u{13f5}"),
+    ('\u{1c80}', "\u{0432}"),
+    ('\u{1c81}', "\u{0434}"),
+    ('\u{1c82}', "\u{043e}"),
+    ('\u{1c83}', "\u{0441}"),
+    ('\u{1c84}', "\u{0442}"),
+    ('\u{1c85}', "\u{0442}"),
+    ('\u{1c86}', "\u{044a}"),
+    ('\u{1c87}', "\u{0463}"),
+    ('\u{1c88}', "\u{a64b}"),
+    ('\u{1e96}', "h\u{0331}"),
+    ('\u{1e97}', "t\u{0308}"),
+    ('\u{1e98}', "w\u{030a}"),
+    ('\u{1e99}', "y\u{030a}"),
+    ('\u{1e9a}', "a\u{02be}"),
+    ('\u{1e9b}', "\u{1e61}"),
+    ('\u{1e9e}', "ss"),
+    ('\u{1f50}', "\u{03c5}\u{0313}"),
+    ('\u{1f52}', "\u{03c5}\u{0313}\u{0300}"),
+    ('\u{1f54}', "\u{03c5}\u{0313}\u{0301}"),
+    ('\u{1f56}', "\u{03c5}\u{0313}\u{0342}"),
+    ('\u{1f80}', "\u{1f00}\u{03b9}"),
+    ('\u{1f81}', "\u{1f01}\u{03b9}"),
+    ('\u{1f82}', "\u{1f02}\u{03b9}"),
+    ('\u{1f83}', "\u{1f03}\u{03b9}"),
+    ('\u{1f84}', "\u{1f04}\u{03b9}"),
+    ('\u{1f85}', "\u{1f05}\u{03b9}"),
+    ('\u{1f86}', "\u{1f06}\u{03b9}"),
+    ('\u{1f87}', "\u{1f07}\u{03b9}"),
+    ('\u{1f88}', "\u{1f00}\u{03b9}"),
+    ('\u{1f89}', "\u{1f01}\u{03b9}"),
+    ('\u{1f8a}', "\u{1f02}\u{03b9}"),
+    ('\u{1f8b}', "\u{1f03}\u{03b9}"),
+    ('\u{1f8c}', "\u{1f04}\u{03b9}"),
+    ('\u{1f8d}', "\u{1f05}\u{03b9}"),
+    ('\u{1f8e}', "\u{1f06}\u{03b9}"),
+    ('\u{1f8f}', "\u{1f07}\u{03b9}"),
+    ('\u{1f90}', "\u{1f20}\u{03b9}"),
+    ('\u{1f91}', "\u{1f21}\u{03b9}"),
+    ('\u{1f92}', "\u{1f22}\u{03b9}"),
+    ('\u{1f93}', "\u{1f23}\u{03b9}"),
+    ('\u{1f94}', "\u{1f24}\u{03b9}"),
+    ('\u{1f95}', "\u{1f25}\u{03b9}"),
+    ('\u{1f96}', "\u{1f26}\u{03b9}"),
+    ('\u{1f97}', "\u{1f27}\u{03b9}"),
+    ('\u{1f98}', "\u{1f20}\u{03b9}"),
+    ('\u{1f99}', "\u{1f21}\u{03b9}"),
+    ('\u{1f9a}', "\u{1f22}\u{03b9}"),
+    ('\u{1f9b}', "\u{1f23}\u{03b9}"),
+    ('\u{1f9c}', "\u{1f24}\u{03b9}"),
+    ('\u{1f9d}', "\u{1f25}\u{03b9}"),
+    ('\u{1f9e}', "\u{1f26}\u{03b9}"),
+    ('\u{1f9f}', "\u{1f27}\u{03b9}"),
+    ('\u{1fa0}', "\u{1f60}\u{03b9}"),
+    ('\u{1fa1}', "\u{1f61}\u{03b9}"),
+    ('\u{1fa2}', "\u{1f62}\u{03b9}"),
+    ('\u{1fa3}', "\u{1f63}\u{03b9}"),
+    ('\u{1fa4}', "\u{1f64}\u{03b9}"),
+    ('\u{1fa5}', "\u{1f65}\u{03b9}"),
+    ('\u{1fa6}', "\u{1f66}\u{03b9}"),
+    ('\u{1fa7}', "\u{1f67}\u{03b9}"),
+    ('\u{1fa8}', "\u{1f60}\u{03b9}"),
+    ('\u{1fa9}', "\u{1f61}\u{03b9}"),
+    ('\u{1faa}', "\u{1f62}\u{03b9}"),
+    ('\u{1fab}', "\u{1f63}\u{03b9}"),
+    ('\u{1fac}', "\u{1f64}\u{03b9}"),
+    ('\u{1fad}', "\u{1f65}\u{03b9}"),
+    ('\u{1fae}', "\u{1f66}\u{03b9}"),
+    ('\u{1faf}', "\u{1f67}\u{03b9}"),
+    ('\u{1fb2}', "\u{1f70}\u{03b9}"),
+    ('\u{1fb3}', "\u{03b1}\u{03b9}"),
+    ('\u{1fb4}', "\u{03ac}\u{03b9}"),
+    ('\u{1fb6}', "\u{03b1}\u{0342}"),
+    ('\u{1fb7}', "\u{03b1}\u{0342}\u{03b9}"),
+    ('\u{1fbc}', "\u{03b1}\u{03b9}"),
+    ('\u{1fbe}', "\u{03b9}"),
+    ('\u{1fc2}', "\u{1f74}\u{03b9}"),
+    ('\u{1fc3}', "\u{03b7}\u{03b9}"),
+    ('\u{1fc4}', "\u{03ae}\u{03b9}"),
+    ('\u{1fc6}', "\u{03b7}\u{0342}"),
+    ('\u{1fc7}', "\u{03b7}\u{0342}\u{03b9}"),
+    ('\u{1fcc}', "\u{03b7}\u{03b9}"),
+    ('\u{1fd2}', "\u{03b9}\u{0308}\u{0300}"),
+    ('\u{1fd3}', "\u{03b9}\u{0308}\u{0301}"),
+    ('\u{1fd6}', "\u{03b9}\u{0342}"),
+    ('\u{1fd7}', "\u{03b9}\u{0308}\u{0342}"),
+    ('\u{1fe2}', "\u{03c5}\u{0308}\u{0300}"),
+    ('\u{1fe3}', "\u{03c5}\u{0308}\u{0301}"),
+    ('\u{1fe4}', "\u{03c1}\u{0313}"),
+    ('\u{1fe6}', "\u{03c5}\u{0342}"),
+    ('\u{1fe7}', "\u{03c5}\u{0308}\u{0342}"),
+    ('\u{1ff2}', "\u{1f7c}\u{03b9}"),
+    ('\u{1ff3}', "\u{03c9}\u{03b9}"),
+    ('\u{1ff4}', "\u{03ce}\u{03b9}"),
+    ('\u{1ff6}', "\u{03c9}\u{0342}"),
+    ('\u{1ff7}', "\u{03c9}\u{0342}\u{03b9}"),
+    ('\u{1ffc}', "\u{03c9}\u{03b9}"),
+    ('\u{ab70}', "\u{13a0}"),
+    ('\u{ab71}', "\u{13a1}"),
+    ('\u{ab72}', "\u{13a2}"),
+    ('\u{ab73}', "\u{13a3}"),
+    ('\u{ab74}', "\u{13a4}"),
+    ('\u{ab75}', "\u{13a5}"),
+    ('\u{ab76}', "\u{13a6}"),
+    ('\u{ab77}', "\u{13a7}"),
+    ('\u{ab78}', "\u{13a8}"),
+    ('\u{ab79}', "\u{13a9}"),
+    ('\u{ab7a}', "\u{13aa}"),
+    ('\u{ab7b}', "\u{13ab}"),
+    ('\u{ab7c}', "\u{13ac}"),
+    ('\u{ab7d}', "\u{13ad}"),
+    ('\u{ab7e}', "\u{13ae}"),
+    ('\u{ab7f}', "\u{13af}"),
+    ('\u{ab80}', "\u{13b0}"),
+    ('\u{ab81}', "\u{13b1}"),
+    ('\u{ab82}', "\u{13b2}"),
+    ('\u{ab83}', "\u{13b3}"),
+    ('\u{ab84}', "\u{13b4}"),
+    ('\u{ab85}', "\u{13b5}"),
+    ('\u{ab86}', "\u{13b6}"),
+    ('\u{ab87}', "\u{13b7}"),
+    ('\u{ab88}', "\u{13b8}"),
+    ('\u{ab89}', "\u{13b9}"),
+    ('\u{ab8a}', "\u{13ba}"),
+    ('\u{ab8b}', "\u{13bb}"),
+    ('\u{ab8c}', "\u{13bc}"),
+    ('\u{ab8d}', "\u{13bd}"),
+    ('\u{ab8e}', "\u{13be}"),
+    ('\u{ab8f}', "\u{13bf}"),
+    ('\u{ab90}', "\u{13c0}"),
+    ('\u{ab91}', "\u{13c1}"),
+    ('\u{ab92}', "\u{13c2}"),
+    ('\u{ab93}', "\u{13c3}"),
+    ('\u{ab94}', "\u{13c4}"),
+    ('\u{ab95}', "\u{13c5}"),
+    ('\u{ab96}', "\u{13c6}"),
+    ('\u{ab97}', "\u{13c7}"),
+    ('\u{ab98}', "\u{13c8}"),
+    ('\u{ab99}', "\u{13c9}"),
+    ('\u{ab9a}', "\u{13ca}"),
+    ('\u{ab9b}', "\u{13cb}"),
+    ('\u{ab9c}', "\u{13cc}"),
+    ('\u{ab9d}', "\u{13cd}"),
+    ('\u{ab9e}', "\u{13ce}"),
+    ('\u{ab9f}', "\u{13cf}"),
+    ('\u{aba0}', "\u{13d0}"),
+    ('\u{aba1}', "\u{13d1}"),
+    ('\u{aba2}', "\u{13d2}"),
+    ('\u{aba3}', "\u{13d3}"),
+    ('\u{aba4}', "\u{13d4}"),
+    ('\u{aba5}', "\u{13d5}"),
+    ('\u{aba6}', "\u{13d6}"),
+    ('\u{aba7}', "\u{13d7}"),
+    ('\u{aba8}', "\u{13d8}"),
+    ('\u{aba9}', "\u{13d9}"),
+    ('\u{abaa}', "\u{13da}"),
+    ('\u{abab}', "\u{13db}"),
+    ('\u{abac}', "\u{13dc}"),
+    ('\u{abad}', "\u{13dd}"),
+    ('\u{abae}', "\u{13de}"),
+    ('\u{abaf}', "\u{13df}"),
+    ('\u{abb0}', "\u{13e0}"),
+    ('\u{abb1}', "\u{13e1}"),
+    ('\u{abb2}', "\u{13e2}"),
+    ('\u{abb3}', "\u{13e3}"),
+    ('\u{abb4}', "\u{13e4}"),
+    ('\u{abb5}', "\u{13e5}"),
+    ('\u{abb6}', "\u{13e6}"),
+    ('\u{abb7}', "\u{13e7}"),
+    ('\u{abb8}', "\u{13e8}"),
+    ('\u{abb9}', "\u{13e9}"),
+    ('\u{abba}', "\u{13ea}"),
+    ('\u{abbb}', "\u{13eb}"),
+    ('\u{abbc}', "\u{13ec}"),
+    ('\u{abbd}', "\u{13ed}"),
+    ('\u{abbe}', "\u{13ee}"),
+    ('\u{abbf}', "\u{13ef}"),
+    ('\u{fb00}', "ff"),
+    ('\u{fb01}', "fi"),
+    ('\u{fb02}', "fl"),
+    ('\u{fb03}', "ffi"),
+    ('\u{fb04}', "ffl"),
+    ('\u{fb05}', "st"),
+    ('\u{fb06}', "st"),
+    ('\u{fb13}', "\u{0574}\u{0576}"),
+    ('\u{fb14}', "\u{0574}\u{0565}"),
+    ('\u{fb15}', "\u{0574}\u{056b}"),
+    ('\u{fb16}', "\u{057e}\u{0576}"),
+    ('\u{fb17}', "\u{0574}\u{056d}"),
+];
@@ -0,0 +1,323 @@
+// --follow: keep searching the files as they grow, like `tail -F file | cli-proj QUERY -` but for
+// every file at once, printing each new matching line as soon as it's written.
+//
+// It's plain polling: every POLL_INTERVAL each file is checked for new data and the directories are
+// walked again for new files. That's a little slower to notice changes than inotify and friends, but
+// needs nothing platform specific and copes with everything that happens to log files:
+//   - appends are read from where the last poll stopped, and a line is only searched once its
+//     newline has arrived, so a line written in two halves is still one line
+//   - truncation (the file is now shorter than what was read) starts over from the top
+//   - rotation (the path now names a different file, i.e. app.log was renamed to app.log.1 and a new
+//     app.log created) finishes reading the old file through the handle that's still open, then starts
+//     on the new one. On unix files are told apart by device and inode, so the renamed file isn't
+//     searched again from the start when it turns up under its new name in a directory being followed.
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::decompress::Compression;
+use crate::printer::Printer;
+use crate::stream::trim_line;
+use crate::{search_path, walk, Config, Match, Matcher};
+
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// which file a path refers to right now
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FileId {
+    device: u64,
+    inode: u64,
+}
+
+fn file_id(metadata: &fs::Metadata) -> Option<FileId> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some(FileId {
+            device: metadata.dev(),
+            inode: metadata.ino(),
+        })
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+// A file being followed, open so that it can still be read to the end after it's been renamed or deleted.
+struct Followed {
+    file: File,
+    id: Option<FileId>,
+    // how much of the file has been read
+    pos: u64,
+    // the start of a line whose newline hasn't been written yet
+    partial: Vec<u8>,
+    line_number: usize,
+}
+
+impl Followed {
+    fn open(path: &Path) -> io::Result<Followed> {
+        let file = File::open(path)?;
+        let id = file_id(&file.metadata()?);
+        Ok(Followed {
+            file,
+            id,
+            pos: 0,
+            partial: Vec::new(),
+            line_number: 0,
+        })
+    }
+
+    // Searches whatever has been appended since the last time, returning whether a line matched.
+    fn read_new<W: Write>(
+        &mut self,
+        path: &Path,
+        matcher: &Matcher,
+        printer: &mut Printer<W>,
+    ) -> io::Result<bool> {
+        self.file.seek(SeekFrom::Start(self.pos))?;
+        let mut reader = BufReader::new(&self.file);
+        let mut selected = false;
+        loop {
+            let read = reader.read_until(b'\n', &mut self.partial)?;
+            if read == 0 {
+                break;
+            }
+            self.pos += read as u64;
+            if !self.partial.ends_with(b"\n") {
+                // the rest of the line is still to come, it'll be searched once it's complete
+                break;
+            }
+            self.line_number += 1;
+            let byte_offset = self.pos as usize - self.partial.len();
+            // searched as raw bytes and trimmed the same way as stream.rs does
+            let line = trim_line(&self.partial);
+            if matcher.is_match(line) {
+                selected = true;
+                let m = Match {
+                    line_number: self.line_number,
+                    byte_offset,
+                    columns: matcher.find_all(line),
+                    patterns: matcher.patterns_in(line),
+                    distance: matcher.distance(line),
+                    line,
+                };
+                printer.print_match(path, &m)?;
+            }
+            self.partial.clear();
+        }
+        Ok(selected)
+    }
+}
+
+pub struct Follower<'a> {
+    config: &'a Config,
+    matcher: Matcher,
+    files: BTreeMap<PathBuf, Followed>,
+    // compressed and binary files can't be followed line by line, they're searched once when they
+    // turn up (the normal way) and then left alone
+    searched_once: HashSet<PathBuf>,
+}
+
+impl<'a> Follower<'a> {
+    pub fn new(config: &'a Config) -> Follower<'a> {
+        Follower {
+            config,
+            matcher: config.matcher(),
+            files: BTreeMap::new(),
+            searched_once: HashSet::new(),
+        }
+    }
+
+    // One round of checking every file for changes, returning whether any new line matched.
+    // The first round searches everything that's already there.
+    pub fn poll<W: Write>(&mut self, printer: &mut Printer<W>) -> io::Result<bool> {
+        let mut selected = false;
+
+        // 1. catch up with the files already being followed, setting aside the ones that have
+        // been rotated or deleted (after reading what was written to them before that happened)
+        let mut moved = Vec::new();
+        for (path, mut followed) in std::mem::take(&mut self.files) {
+            match fs::metadata(&path).ok() {
+                Some(metadata) if file_id(&metadata) == followed.id => {
+                    if metadata.len() < followed.pos {
+                        // truncated, i.e. `> app.log`: start over
+                        followed.pos = 0;
+                        followed.partial.clear();
+                        followed.line_number = 0;
+                    }
+                    selected |= followed.read_new(&path, &self.matcher, printer)?;
+                    self.files.insert(path, followed);
+                }
+                _ => {
+                    selected |= followed.read_new(&path, &self.matcher, printer)?;
+                    moved.push(followed);
+                }
+            }
+        }
+
+        // 2. pick up new files (and the new file behind a rotated path). One that's really a file
+        // already being followed under another name carries on from where it was.
+        for path in self.current_files()? {
+            if self.files.contains_key(&path) || self.searched_once.contains(&path) {
+                continue;
+            }
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            let id = file_id(&metadata);
+            if let Some(i) = moved.iter().position(|m| m.id.is_some() && m.id == id) {
+                let followed = moved.swap_remove(i);
+                self.files.insert(path, followed);
+                continue;
+            }
+            let mut followed = match Followed::open(&path) {
+                Ok(followed) => followed,
+                // gone again already
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let head = BufReader::new(&followed.file).fill_buf()?.to_vec();
+            if walk::is_binary(&head) || Compression::detect(&head).is_some() {
+                selected |= search_path(&self.matcher, &path, printer)?;
+                self.searched_once.insert(path);
+                continue;
+            }
+            selected |= followed.read_new(&path, &self.matcher, printer)?;
+            self.files.insert(path, followed);
+        }
+        // whatever in `moved` didn't turn up under a new name has been deleted, and is finished with
+        Ok(selected)
+    }
+
+    // The files to follow right now. A file named on the command line can be missing for a moment
+    // while it's being rotated, that's not an error.
+    fn current_files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for path in &self.config.paths {
+            match walk::collect_files(std::slice::from_ref(path), &self.config.filter) {
+                Ok(found) => files.extend(found),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(files)
+    }
+}
+
+// Runs until it's interrupted (or a file can't be read), so it only ever returns an error.
+pub fn follow<W: Write>(config: &Config, show_path: bool, out: W) -> io::Result<()> {
+    let mut printer = config.printer(out).with_paths(show_path);
+    let mut follower = Follower::new(config);
+    loop {
+        follower.poll(&mut printer)?;
+        printer.flush()?;
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+
+    fn config(path: &Path) -> Config {
+        let args = ["cli-proj", "--follow", "timeout", path.to_str().unwrap()];
+        Config::new(args.iter().map(|s| s.to_string())).unwrap()
+    }
+
+    // what one round prints, with the file name in front to tell files apart
+    fn poll(follower: &mut Follower, config: &Config) -> String {
+        let mut printer = config.printer(Vec::new()).with_paths(true);
+        follower.poll(&mut printer).unwrap();
+        String::from_utf8(printer.into_inner()).unwrap()
+    }
+
+    fn append(path: &Path, text: &str) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn prints_appended_lines_once_complete() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "timeout 1\nok\n");
+        let config = config(&log);
+        let mut follower = Follower::new(&config);
+        let name = log.display();
+
+        assert_eq!(
+            format!("{}:timeout 1\n", name),
+            poll(&mut follower, &config)
+        );
+        assert_eq!("", poll(&mut follower, &config));
+        // half a line waits for the rest of it
+        append(&log, "ok\ntime");
+        assert_eq!("", poll(&mut follower, &config));
+        append(&log, "out 2\n");
+        assert_eq!(
+            format!("{}:timeout 2\n", name),
+            poll(&mut follower, &config)
+        );
+    }
+
+    #[test]
+    fn starts_over_after_truncation() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "timeout 1\nok\nok\n");
+        let config = config(&log);
+        let mut follower = Follower::new(&config);
+        poll(&mut follower, &config);
+
+        fs::write(&log, "timeout 2\n").unwrap();
+        assert_eq!(
+            format!("{}:timeout 2\n", log.display()),
+            poll(&mut follower, &config)
+        );
+    }
+
+    #[test]
+    fn follows_rotation_and_new_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        let rotated = dir.path().join("app.log.1");
+        append(&log, "timeout 1\n");
+        let config = config(dir.path());
+        let mut follower = Follower::new(&config);
+        poll(&mut follower, &config);
+
+        // written just before the rotation, then app.log starts afresh
+        append(&log, "timeout 2\n");
+        fs::rename(&log, &rotated).unwrap();
+        append(&log, "timeout 3\n");
+        append(&dir.path().join("other.log"), "timeout 4\n");
+        let out = poll(&mut follower, &config);
+
+        // the old file is read to the end (under the name it had) before the new app.log is started on
+        let mut lines: Vec<String> = out.lines().map(String::from).collect();
+        lines.sort_unstable();
+        let mut expected = vec![
+            format!("{}:timeout 2", log.display()),
+            format!("{}:timeout 3", log.display()),
+            format!("{}:timeout 4", dir.path().join("other.log").display()),
+        ];
+        expected.sort_unstable();
+        assert_eq!(expected, lines);
+        // the rotated file is still the same file, it isn't searched again from the top
+        append(&rotated, "timeout 5\n");
+        assert_eq!(
+            format!("{}:timeout 5\n", rotated.display()),
+            poll(&mut follower, &config)
+        );
+    }
+}
@@ -0,0 +1,194 @@
+// Approximate matching (--fuzzy N): a line matches if some part of it is within N edits of the query,
+// where an edit is inserting, deleting or changing one char. So "recived" and "receeved" both find "received".
+// This is Sellers' variant of the Levenshtein distance: the usual edit distance table, except a match may
+// start anywhere in the line for free, so it measures the query against the closest substring rather than
+// the whole line.
+use std::ops::Range;
+
+use crate::fold::fold_if;
+use crate::utf8;
+
+pub struct FuzzyQuery {
+    // compared char by char, already folded when case doesn't matter
+    query: Vec<char>,
+    case_sensitive: bool,
+    max_distance: usize,
+}
+
+// one column of the table: the edit distance of the best match of the first i chars of the query
+// ending here, and the char index in the line where that match starts
+#[derive(Clone, Copy)]
+struct Cell {
+    distance: usize,
+    start: usize,
+}
+
+impl FuzzyQuery {
+    pub fn new(query: &str, max_distance: usize, case_sensitive: bool) -> FuzzyQuery {
+        FuzzyQuery {
+            query: query
+                .chars()
+                .flat_map(|c| fold_if(c, !case_sensitive))
+                .collect(),
+            case_sensitive,
+            max_distance,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.query.is_empty()
+    }
+
+    // The smallest number of edits between the query and any part of the line,
+    // if it's within the limit. Lines are ranked by this with --sort-distance.
+    pub fn distance(&self, line: &[u8]) -> Option<usize> {
+        let chars = self.chars(line);
+        let mut best = self.query.len();
+        self.scan(&chars, 0, |distance, _| {
+            best = best.min(distance);
+            false
+        });
+        (best <= self.max_distance).then_some(best)
+    }
+
+    // The first approximate match at or after byte `from`: found at the leftmost place where the
+    // distance is within the limit, then extended for as long as the next char doesn't make it worse
+    // (so with --fuzzy 1, "received" highlights all of "received" in a line, not the "receive" that
+    // was already close enough one char earlier). A hit that doesn't overlap it is another match
+    // further on, however much closer it is, and is left for the next call.
+    pub fn find_from(&self, line: &[u8], from: usize) -> Option<Range<usize>> {
+        let chars = self.chars(line);
+        let first = chars.iter().position(|&(offset, _, _)| offset >= from)?;
+        let mut found: Option<(usize, Range<usize>)> = None;
+        self.scan(&chars, first, |distance, range| {
+            if found
+                .as_ref()
+                .is_some_and(|(best, at)| distance > *best || range.start >= at.end)
+            {
+                return true;
+            }
+            found = Some((distance, range));
+            false
+        });
+        found.map(|(_, range)| range)
+    }
+
+    // the folded chars of the line, each with the byte range of the char it came from
+    fn chars(&self, line: &[u8]) -> Vec<(usize, usize, char)> {
+        utf8::char_indices(line)
+            .flat_map(|(at, c)| {
+                fold_if(c, !self.case_sensitive).map(move |f| (at.start, at.end, f))
+            })
+            .collect()
+    }
+
+    // Fills in the table a column (one char of the line) at a time, starting at chars[first].
+    // Every time a match within the limit ends, `hit` gets its distance and byte range; it returns
+    // true to stop the scan.
+    fn scan(
+        &self,
+        chars: &[(usize, usize, char)],
+        first: usize,
+        mut hit: impl FnMut(usize, Range<usize>) -> bool,
+    ) {
+        let m = self.query.len();
+        // before any of the line: matching the first i chars of the query means deleting all of them
+        let mut column: Vec<Cell> = (0..=m)
+            .map(|i| Cell {
+                distance: i,
+                start: first,
+            })
+            .collect();
+        for j in first..chars.len() {
+            let c = chars[j].2;
+            // a match can start right here at no cost
+            let mut diagonal = column[0];
+            column[0] = Cell {
+                distance: 0,
+                start: j + 1,
+            };
+            for i in 1..=m {
+                let substitute = Cell {
+                    distance: diagonal.distance + (self.query[i - 1] != c) as usize,
+                    start: diagonal.start,
+                };
+                // skip a char of the line, or one of the query
+                let insert = Cell {
+                    distance: column[i].distance + 1,
+                    start: column[i].start,
+                };
+                let delete = Cell {
+                    distance: column[i - 1].distance + 1,
+                    start: column[i - 1].start,
+                };
+                diagonal = column[i];
+                column[i] = [insert, delete].into_iter().fold(substitute, |best, cell| {
+                    if cell.distance < best.distance {
+                        cell
+                    } else {
+                        best
+                    }
+                });
+            }
+            let end = column[m];
+            // only whole chars count: not when the match stops or starts halfway through a folded "ß",
+            // and not when it's nothing but deleting the whole query (there's nothing to point at)
+            let whole = end.start <= j
+                && chars.get(j + 1).is_none_or(|next| next.0 != chars[j].0)
+                && (end.start == 0 || chars[end.start - 1].0 != chars[end.start].0);
+            if end.distance <= self.max_distance
+                && whole
+                && hit(end.distance, chars[end.start].0..chars[j].1)
+            {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_to_the_closest_part_of_the_line() {
+        let query = FuzzyQuery::new("received", 2, true);
+
+        assert_eq!(Some(0), query.distance(b"we received it"));
+        assert_eq!(Some(1), query.distance(b"we recived it"));
+        assert_eq!(Some(1), query.distance(b"we receeved it"));
+        // swapping two chars is two edits
+        assert_eq!(Some(2), query.distance(b"we recieved it"));
+        assert_eq!(Some(2), query.distance(b"we rceivd it"));
+        assert_eq!(None, query.distance(b"we got it"));
+    }
+
+    #[test]
+    fn finds_whole_typo() {
+        let query = FuzzyQuery::new("received", 1, true);
+
+        assert_eq!(Some(3..11), query.find_from(b"we received it", 0));
+        assert_eq!(Some(3..10), query.find_from(b"we recived it", 0));
+        assert_eq!(Some(3..11), query.find_from(b"we receeved it", 0));
+        assert_eq!(None, query.find_from(b"we recived it", 4));
+    }
+
+    #[test]
+    fn leftmost_match_wins_over_a_closer_one_later() {
+        let query = FuzzyQuery::new("received", 1, true);
+        let line = b"we recived it and received again";
+
+        assert_eq!(Some(3..10), query.find_from(line, 0));
+        assert_eq!(Some(18..26), query.find_from(line, 10));
+        // the line as a whole is still as close as its closest part
+        assert_eq!(Some(0), query.distance(line));
+    }
+
+    #[test]
+    fn folds_case() {
+        let query = FuzzyQuery::new("STRASE", 1, false);
+
+        assert_eq!(Some(1), query.distance("straße".as_bytes()));
+        assert_eq!(Some(0..7), query.find_from("Straße".as_bytes(), 0));
+    }
+}
@@ -0,0 +1,555 @@
+// `cli-proj index DIR`: a trigram index of every file under DIR, so repeated searches of a big, mostly
+// unchanging tree can skip the files that can't possibly match without opening them.
+//
+// A trigram is three consecutive chars. If a line contains "timeout", the file contains the trigrams
+// "tim", "ime", "meo", "eou" and "out"; so a file missing any of them can't contain "timeout" and doesn't
+// need to be searched. The index records each file's trigrams, and a search works out which trigrams the
+// query needs (see `required`) and only reads the files that have them all. Everything that's left is
+// still searched normally, so the index only ever saves work: it never decides what matches.
+//
+// Trigrams are taken after case folding (fold.rs), so one index serves case-sensitive and -i searches,
+// and of the chars the search sees (decompressed, with each invalid UTF-8 sequence as a U+FFFD, see utf8.rs).
+// A regex whose literal part isn't UTF-8 (a raw byte like \xff) can't be looked up, and rules nothing out.
+//
+// Files are only re-read when they change: each entry keeps the file's size and modification time, and
+// `cli-proj index` on an existing index reuses the entries that still match. A search treats a file whose
+// size or mtime no longer matches its entry (or that has no entry) as a candidate, so a stale index can
+// make a search slower but never wrong.
+//
+// Except for one race: mtimes only go up in steps (a nanosecond on most filesystems, but a second or two
+// on some), so a file written again within the same step as it was indexed keeps its mtime, and with the
+// same size it would look unchanged. So a file modified less than RACY_WINDOW before it's indexed is left
+// out of the index, which makes it a candidate for every search until a later `cli-proj index` adds it.
+// By then any change to it after it was indexed has to have moved its mtime on.
+//
+// The index lives in DIR/.cli-proj-index (hidden, so it isn't searched itself). The format, with every
+// integer little-endian:
+//
+//   magic        8 bytes   "CPIDX\0\0\x01" (the last byte is the format version)
+//   file count   u32
+//   then for each file, sorted by path:
+//     path length  u32, then the path relative to DIR in UTF-8, with / between components
+//     size         u64       file size in bytes
+//     mtime        u64, u32  modification time: seconds and nanoseconds since the Unix epoch
+//     trigrams     u32 count, then that many u64s in increasing order
+//
+// A trigram is stored as its three chars' code points packed 21 bits apiece: (a << 42) | (b << 21) | c.
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use regex_syntax::hir::literal::Extractor;
+use regex_syntax::ParserBuilder;
+
+use crate::fold::fold_if;
+use crate::utf8;
+use crate::{decompress, replace, walk, Config, Filter, OutputMode};
+
+pub const INDEX_FILE: &str = ".cli-proj-index";
+const MAGIC: &[u8; 8] = b"CPIDX\0\0\x01";
+// longer than the coarsest mtime step around (FAT's two seconds)
+const RACY_WINDOW: Duration = Duration::from_secs(3);
+
+// what a file looked like when it was indexed, to tell whether it has changed since
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Stamp {
+    size: u64,
+    secs: u64,
+    nanos: u32,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> io::Result<Stamp> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(Stamp {
+            size: metadata.len(),
+            secs: modified.as_secs(),
+            nanos: modified.subsec_nanos(),
+        })
+    }
+
+    // modified too recently for the stamp to be sure to change with the next write (see RACY_WINDOW),
+    // or, with the clock set back, in the future
+    fn is_racy(&self) -> bool {
+        let modified = UNIX_EPOCH + Duration::new(self.secs, self.nanos);
+        SystemTime::now()
+            .duration_since(modified)
+            .ok()
+            .is_none_or(|age| age < RACY_WINDOW)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Entry {
+    stamp: Stamp,
+    // sorted, so a lookup is a binary search
+    trigrams: Vec<u64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Index {
+    root: PathBuf,
+    entries: BTreeMap<String, Entry>,
+}
+
+// What `cli-proj index` did, for the line it prints.
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub files: usize,
+    // new or changed since the last time, the only files that had to be read
+    pub read: usize,
+    // gone since the last time
+    pub removed: usize,
+    // modified just now, and left out until the next time (see RACY_WINDOW)
+    pub racy: usize,
+}
+
+impl Index {
+    // The index of `root`, or None if it hasn't been indexed.
+    pub fn load(root: &Path) -> io::Result<Option<Index>> {
+        let path = root.join(INDEX_FILE);
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let entries = decode(&bytes).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is not a valid index, run `cli-proj index {}` to rebuild it",
+                    path.display(),
+                    root.display()
+                ),
+            )
+        })?;
+        Ok(Some(Index {
+            root: root.to_path_buf(),
+            entries,
+        }))
+    }
+
+    // Builds the index of `root`, or brings an existing one up to date, and writes it out.
+    // The same files are indexed as a search of `root` would look at (ignore files, hidden files).
+    pub fn update(root: &Path) -> io::Result<Summary> {
+        let old = Index::load(root)
+            .ok()
+            .flatten()
+            .map(|index| index.entries)
+            .unwrap_or_default();
+        let files = walk::collect_files(&[root.display().to_string()], &Filter::default())?;
+
+        let mut summary = Summary::default();
+        let mut entries = BTreeMap::new();
+        for file in files {
+            let Some(key) = key(root, &file) else {
+                continue;
+            };
+            let stamp = Stamp::of(&file)?;
+            if stamp.is_racy() {
+                summary.racy += 1;
+                continue;
+            }
+            let entry = match old.get(&key) {
+                Some(entry) if entry.stamp == stamp => Entry {
+                    stamp,
+                    trigrams: entry.trigrams.clone(),
+                },
+                _ => {
+                    summary.read += 1;
+                    Entry {
+                        stamp,
+                        trigrams: file_trigrams(&file)?,
+                    }
+                }
+            };
+            entries.insert(key, entry);
+        }
+        summary.files = entries.len();
+        summary.removed = old.keys().filter(|key| !entries.contains_key(*key)).count();
+
+        replace::write_atomically(&root.join(INDEX_FILE), &encode(&entries))?;
+        Ok(summary)
+    }
+
+    // Whether `file` is known not to match: it's in the index, hasn't changed since, and has none of
+    // the sets of trigrams in `required` in full.
+    fn rules_out(&self, file: &Path, required: &[Vec<u64>]) -> bool {
+        let Some(entry) = key(&self.root, file).and_then(|key| self.entries.get(&key)) else {
+            return false;
+        };
+        if Stamp::of(file).ok() != Some(entry.stamp) {
+            return false;
+        }
+        !required.iter().any(|trigrams| {
+            trigrams
+                .iter()
+                .all(|t| entry.trigrams.binary_search(t).is_ok())
+        })
+    }
+}
+
+// Drops the files the indexes of the directories being searched rule out, returning the files left
+// and how many were dropped. Only done when a file that can't match wouldn't print anything anyway:
+// -c and -L report on every file, and with -v it's the files without the query that are interesting.
+pub fn narrow(config: &Config, mut files: Vec<PathBuf>) -> io::Result<(Vec<PathBuf>, usize)> {
+    if !config.use_index
+        || config.invert
+        || matches!(
+            config.mode,
+            OutputMode::Count | OutputMode::FilesWithoutMatch
+        )
+    {
+        return Ok((files, 0));
+    }
+    let Some(required) = required(config) else {
+        return Ok((files, 0));
+    };
+    let mut indexes = Vec::new();
+    for path in &config.paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            indexes.extend(Index::load(path)?);
+        }
+    }
+    let before = files.len();
+    if !indexes.is_empty() {
+        files.retain(|file| !indexes.iter().any(|index| index.rules_out(file, &required)));
+    }
+    let ruled_out = before - files.len();
+    Ok((files, ruled_out))
+}
+
+// The trigrams a matching file must have, as alternatives: a file can match if it has every trigram
+// of at least one of the sets. None when nothing can be ruled out, i.e. a pattern shorter than three
+// chars, --fuzzy (a typo can be anywhere), or a regex without a literal every match has to start with.
+fn required(config: &Config) -> Option<Vec<Vec<u64>>> {
+    if config.fuzzy.is_some() {
+        return None;
+    }
+    let mut alternatives = Vec::new();
+    for pattern in &config.patterns {
+        if config.regex.is_none() {
+            alternatives.push(pattern.clone());
+            continue;
+        }
+        // the same regex the search compiles; utf8(false) lets a (?-u:\xe9) byte through, though a
+        // literal that isn't UTF-8 can't be looked up and leaves the search unnarrowed
+        let hir = ParserBuilder::new()
+            .case_insensitive(!config.case_sensitive)
+            .utf8(false)
+            .build()
+            .parse(pattern)
+            .ok()?;
+        // every match of the regex starts with one of these
+        let prefixes = Extractor::new().extract(&hir);
+        for literal in prefixes.literals()? {
+            alternatives.push(String::from_utf8(literal.as_bytes().to_vec()).ok()?);
+        }
+    }
+    alternatives
+        .iter()
+        .map(|literal| {
+            let trigrams = trigrams_of(literal.chars());
+            (!trigrams.is_empty()).then(|| trigrams.into_iter().collect())
+        })
+        .collect()
+}
+
+fn file_trigrams(file: &Path) -> io::Result<Vec<u64>> {
+    let mut reader = decompress::reader(BufReader::new(File::open(file)?))?;
+    let mut trigrams = HashSet::new();
+    // line by line like stream.rs, so a huge file isn't read into memory whole
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        trigrams.extend(trigrams_of(utf8::char_indices(&buf).map(|(_, c)| c)));
+    }
+    let mut trigrams: Vec<u64> = trigrams.into_iter().collect();
+    trigrams.sort_unstable();
+    Ok(trigrams)
+}
+
+// the trigrams of a piece of text after case folding, leaving out any that span a line break
+fn trigrams_of(text: impl Iterator<Item = char>) -> HashSet<u64> {
+    let mut trigrams = HashSet::new();
+    let mut window = [None; 3];
+    for c in text.flat_map(|c| fold_if(c, true)) {
+        window = [window[1], window[2], Some(c)];
+        if let [Some(a), Some(b), Some(c)] = window {
+            if ![a, b, c].contains(&'\n') {
+                trigrams.insert(pack(a, b, c));
+            }
+        }
+    }
+    trigrams
+}
+
+fn pack(a: char, b: char, c: char) -> u64 {
+    (a as u64) << 42 | (b as u64) << 21 | c as u64
+}
+
+// a file's path relative to the indexed directory, with / between components whatever the platform
+fn key(root: &Path, file: &Path) -> Option<String> {
+    let relative = file.strip_prefix(root).ok()?;
+    let parts: Vec<&str> = relative
+        .components()
+        .map(|part| part.as_os_str().to_str())
+        .collect::<Option<_>>()?;
+    Some(parts.join("/"))
+}
+
+fn encode(entries: &BTreeMap<String, Entry>) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.extend((entries.len() as u32).to_le_bytes());
+    for (path, entry) in entries {
+        out.extend((path.len() as u32).to_le_bytes());
+        out.extend(path.as_bytes());
+        out.extend(entry.stamp.size.to_le_bytes());
+        out.extend(entry.stamp.secs.to_le_bytes());
+        out.extend(entry.stamp.nanos.to_le_bytes());
+        out.extend((entry.trigrams.len() as u32).to_le_bytes());
+        for trigram in &entry.trigrams {
+            out.extend(trigram.to_le_bytes());
+        }
+    }
+    out
+}
+
+// None if the bytes aren't an index in this format (truncated, another version, ...)
+fn decode(bytes: &[u8]) -> Option<BTreeMap<String, Entry>> {
+    let mut input = Input(bytes.strip_prefix(MAGIC)?);
+    let mut entries = BTreeMap::new();
+    for _ in 0..input.u32()? {
+        let len = input.u32()? as usize;
+        let path = String::from_utf8(input.take(len)?.to_vec()).ok()?;
+        let stamp = Stamp {
+            size: input.u64()?,
+            secs: input.u64()?,
+            nanos: input.u32()?,
+        };
+        let count = input.u32()? as usize;
+        let trigrams = input
+            .take(count.checked_mul(8)?)?
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        entries.insert(path, Entry { stamp, trigrams });
+    }
+    input.0.is_empty().then_some(entries)
+}
+
+// the part of the index file that hasn't been decoded yet
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let (head, rest) = self.0.split_at_checked(n)?;
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(args: &[&str]) -> Config {
+        let mut all = vec!["cli-proj"];
+        all.extend(args);
+        Config::new(all.into_iter().map(String::from)).unwrap()
+    }
+
+    fn trigram(s: &str) -> u64 {
+        let c: Vec<char> = s.chars().collect();
+        pack(c[0], c[1], c[2])
+    }
+
+    // written a while ago, so it isn't too recent to go in the index
+    fn write_old(path: &Path, contents: &str) {
+        fs::write(path, contents).unwrap();
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(an_hour_ago)
+            .unwrap();
+    }
+
+    fn sorted(trigrams: impl IntoIterator<Item = u64>) -> Vec<u64> {
+        let mut trigrams: Vec<u64> = trigrams.into_iter().collect();
+        trigrams.sort_unstable();
+        trigrams
+    }
+
+    #[test]
+    fn trigrams_are_case_folded() {
+        let trigrams = trigrams_of("TimeOut\nab".chars());
+
+        assert_eq!(5, trigrams.len());
+        assert!(trigrams.contains(&trigram("out")));
+        // nothing across the line break
+        assert!(!trigrams.contains(&trigram("t\na")));
+        assert!(trigrams_of("ab".chars()).is_empty());
+    }
+
+    #[test]
+    fn format_round_trip() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            "sub/a.log".to_string(),
+            Entry {
+                stamp: Stamp {
+                    size: 12,
+                    secs: 1_700_000_000,
+                    nanos: 5,
+                },
+                trigrams: vec![trigram("abc"), trigram("bcd")],
+            },
+        );
+        let bytes = encode(&entries);
+
+        assert!(bytes.starts_with(b"CPIDX\0\0\x01\x01\0\0\0\x09\0\0\0sub/a.log"));
+        assert_eq!(Some(entries), decode(&bytes));
+        assert_eq!(None, decode(&bytes[..bytes.len() - 1]));
+        assert_eq!(None, decode(b"CPIDX\0\0\x02\0\0\0\0"));
+    }
+
+    #[test]
+    fn required_trigrams() {
+        let literal = required(&config(&["Time", "f"])).unwrap();
+        assert_eq!(
+            vec![sorted([trigram("tim"), trigram("ime")])],
+            literal.into_iter().map(sorted).collect::<Vec<_>>()
+        );
+        // any one of the patterns can match
+        assert_eq!(
+            2,
+            required(&config(&["-e", "abc", "-e", "xyz", "f"]))
+                .unwrap()
+                .len()
+        );
+        assert_eq!(None, required(&config(&["-e", "abc", "-e", "xy", "f"])));
+        // every match of the regex starts with "error: "
+        assert!(required(&config(&["-E", r"error: \d+", "f"])).is_some());
+        assert_eq!(None, required(&config(&["-E", r"\d+ errors", "f"])));
+        assert_eq!(None, required(&config(&["--fuzzy", "1", "timeout", "f"])));
+    }
+
+    #[test]
+    fn incremental_update_and_narrowing() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write_old(&root.join("a.log"), "connection timeout\n");
+        write_old(&root.join("b.log"), "all good\n");
+        fs::create_dir(root.join("old")).unwrap();
+        write_old(&root.join("old/c.log"), "TIMEOUT again\n");
+
+        assert_eq!(
+            Summary {
+                files: 3,
+                read: 3,
+                removed: 0,
+                racy: 0
+            },
+            Index::update(root).unwrap()
+        );
+        fs::remove_file(root.join("old/c.log")).unwrap();
+        write_old(&root.join("d.log"), "timeout\n");
+        assert_eq!(
+            Summary {
+                files: 3,
+                read: 1,
+                removed: 1,
+                racy: 0
+            },
+            Index::update(root).unwrap()
+        );
+
+        let root_arg = root.to_str().unwrap();
+        let config = config(&["-i", "timeout", root_arg]);
+        let files = walk::collect_files(&config.paths, &config.filter).unwrap();
+        assert_eq!(
+            vec![root.join("a.log"), root.join("d.log")],
+            narrow(&config, files.clone()).unwrap().0
+        );
+        // a file that changed since it was indexed is always a candidate
+        fs::write(root.join("b.log"), "now a timeout too, longer\n").unwrap();
+        assert_eq!(3, narrow(&config, files.clone()).unwrap().0.len());
+        // -c lists every file, so nothing is left out
+        let count = Config::new(
+            ["cli-proj", "-c", "timeout", root_arg]
+                .into_iter()
+                .map(String::from),
+        )
+        .unwrap();
+        assert_eq!(3, narrow(&count, files).unwrap().0.len());
+    }
+
+    #[test]
+    fn ruled_out_files_count_as_searched() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write_old(&root.join("a.log"), "timeout\n");
+        write_old(&root.join("b.log"), "all good\n");
+        let root_arg = root.to_str().unwrap();
+        let config = config(&["--json", "timeout", root_arg]);
+        let files = walk::collect_files(&config.paths, &config.filter).unwrap();
+        let summary = || {
+            let mut out = Vec::new();
+            crate::search_files(&config, &files, true, &mut out).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            out.lines().last().unwrap().to_string()
+        };
+
+        let unindexed = summary();
+        Index::update(root).unwrap();
+        // b.log isn't read any more, but the totals are the same as without the index
+        assert_eq!(unindexed, summary());
+        assert!(unindexed.contains(r#""files_searched":2"#), "{}", unindexed);
+    }
+
+    #[test]
+    fn files_modified_while_indexing_are_left_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write_old(&root.join("a.log"), "all good\n");
+        // written just now, it could be written again without its mtime moving on
+        fs::write(root.join("b.log"), "all good\n").unwrap();
+
+        assert_eq!(
+            Summary {
+                files: 1,
+                read: 1,
+                removed: 0,
+                racy: 1
+            },
+            Index::update(root).unwrap()
+        );
+        fs::write(root.join("b.log"), "timeout!\n").unwrap();
+        let config = config(&["timeout", root.to_str().unwrap()]);
+        let files = walk::collect_files(&config.paths, &config.filter).unwrap();
+        assert_eq!(
+            (vec![root.join("b.log")], 1),
+            narrow(&config, files).unwrap()
+        );
+    }
+}
@@ -0,0 +1,100 @@
+// Just enough JSON writing for --json output, without pulling in a serialization crate.
+// Text that isn't valid UTF-8 can't go in a JSON string, so, like ripgrep, arbitrary data is written as
+// either {"text":"..."} when it's UTF-8 or {"bytes":"<base64>"} when it isn't, and consumers check which key is there.
+use std::path::Path;
+
+// A JSON string literal, quotes included.
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            // every other control character has to be escaped too, JSON doesn't allow them raw
+            c if (c as u32) < 0x20 || c == '\u{7f}' => {
+                out.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub fn data(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => format!("{{\"text\":{}}}", string(text)),
+        Err(_) => format!("{{\"bytes\":\"{}\"}}", base64(bytes)),
+    }
+}
+
+// File names are OS strings, which on unix are arbitrary bytes rather than UTF-8.
+pub fn path(path: &Path) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        data(path.as_os_str().as_bytes())
+    }
+    #[cfg(not(unix))]
+    {
+        data(path.to_string_lossy().as_bytes())
+    }
+}
+
+// standard base64 (RFC 4648) with padding
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(r#""plain""#, string("plain"));
+        assert_eq!(r#""say \"hi\"\\n\n\t""#, string("say \"hi\"\\n\n\t"));
+        assert_eq!(r#""bell\u0007""#, string("bell\u{7}"));
+        assert_eq!(r#""Здравствуйте""#, string("Здравствуйте"));
+    }
+
+    #[test]
+    fn text_or_bytes() {
+        assert_eq!(
+            r#"{"text":"caf\u00e9"}"#.replace("\\u00e9", "é"),
+            data("café".as_bytes())
+        );
+        // "café" in Latin-1 isn't valid UTF-8
+        assert_eq!(r#"{"bytes":"Y2Fm6Q=="}"#, data(b"caf\xe9"));
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!("", base64(b""));
+        assert_eq!("Zg==", base64(b"f"));
+        assert_eq!("Zm8=", base64(b"fo"));
+        assert_eq!("Zm9v", base64(b"foo"));
+        assert_eq!("Zm9vYmFy", base64(b"foobar"));
+    }
+}
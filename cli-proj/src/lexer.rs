@@ -0,0 +1,326 @@
+// --only: searching Rust source by what the text is, not just what it says. A query restricted to
+// identifiers doesn't find the same word in a comment or a string, and the other way around.
+// This is just enough of a Rust lexer to tell identifiers, string literals and comments apart from
+// everything else. It works a line at a time like the rest of the search, carrying over whatever
+// is still open at the end of a line: a block comment (they nest in Rust), a string or a raw string.
+use std::ops::Range;
+
+use crate::utf8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    // names of functions, types, variables... (not keywords, a raw identifier like r#type is one)
+    Identifier,
+    // "..." including b"...", c"..." and raw strings, quotes and all
+    String,
+    // line and block comments, doc comments too
+    Comment,
+}
+
+impl Syntax {
+    pub fn parse(name: &str) -> Option<Syntax> {
+        match name {
+            "identifier" | "ident" => Some(Syntax::Identifier),
+            "string" | "str" => Some(Syntax::String),
+            "comment" => Some(Syntax::Comment),
+            _ => None,
+        }
+    }
+}
+
+// strict and reserved keywords, which look like identifiers but aren't
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while", "abstract", "become", "box", "do", "final", "gen", "macro",
+    "override", "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
+// what's still open at the end of a line
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum State {
+    #[default]
+    Code,
+    // how deeply nested
+    BlockComment(usize),
+    Str,
+    // the number of #s that close it
+    RawStr(usize),
+}
+
+#[derive(Debug, Default)]
+pub struct Lexer {
+    state: State,
+}
+
+impl Lexer {
+    // The parts of the next line that are one of `kinds`, as byte ranges, left to right.
+    // A string or comment that carries on to the next line ends at the end of this one.
+    pub fn next_line(&mut self, bytes: &[u8], kinds: &[Syntax]) -> Vec<Range<usize>> {
+        let mut found = Vec::new();
+        let mut keep = |kind: Syntax, range: Range<usize>| {
+            if kinds.contains(&kind) && !range.is_empty() {
+                found.push(range);
+            }
+        };
+        let mut i = 0;
+        while i < bytes.len() || self.state != State::Code {
+            let start = i;
+            match self.state {
+                State::BlockComment(depth) => {
+                    i = self.block_comment(bytes, i, depth);
+                    keep(Syntax::Comment, start..i);
+                }
+                State::Str => {
+                    i = self.string(bytes, i);
+                    keep(Syntax::String, start..i);
+                }
+                State::RawStr(hashes) => {
+                    i = self.raw_string(bytes, i, hashes);
+                    keep(Syntax::String, start..i);
+                }
+                State::Code => {
+                    let rest = &bytes[i..];
+                    if rest.starts_with(b"//") {
+                        i = bytes.len();
+                        keep(Syntax::Comment, start..i);
+                    } else if rest.starts_with(b"/*") {
+                        i = self.block_comment(bytes, i + 2, 1);
+                        keep(Syntax::Comment, start..i);
+                    } else if let Some((prefix, hashes)) = raw_string_start(rest) {
+                        i = self.raw_string(bytes, i + prefix, hashes);
+                        keep(Syntax::String, start..i);
+                    } else if let Some(prefix) = string_start(rest) {
+                        i = self.string(bytes, i + prefix);
+                        keep(Syntax::String, start..i);
+                    } else if rest[0] == b'\'' {
+                        // a char literal ('a', '\n', '"') or a lifetime ('a), neither is searched
+                        i = char_or_lifetime(bytes, i);
+                    } else if rest.starts_with(b"b'") {
+                        // a byte literal (b'a', b'\''), not an identifier b and then a char
+                        i = char_or_lifetime(bytes, i + 1);
+                    } else if rest[0].is_ascii_digit() {
+                        i = number(bytes, i);
+                    } else if let Some((_, len)) =
+                        utf8::decode(rest).filter(|&(c, _)| is_ident_start(c))
+                    {
+                        let raw = rest.starts_with(b"r#");
+                        let from = if raw { i + 2 } else { i + len };
+                        i = from + ident_len(&bytes[from..]);
+                        let word = &bytes[start..i];
+                        if raw || !KEYWORDS.iter().any(|keyword| keyword.as_bytes() == word) {
+                            keep(Syntax::Identifier, start..i);
+                        }
+                    } else {
+                        i += utf8::decode(rest).map_or(1, |(_, len)| len);
+                    }
+                }
+            }
+            if i >= bytes.len() {
+                break;
+            }
+        }
+        found
+    }
+
+    // Reads a block comment from `i` (just past its opening, or the start of a line inside it)
+    // to just past its closing, or to the end of the line if it doesn't close on this one.
+    fn block_comment(&mut self, bytes: &[u8], mut i: usize, mut depth: usize) -> usize {
+        while i < bytes.len() {
+            if bytes[i..].starts_with(b"/*") {
+                depth += 1;
+                i += 2;
+            } else if bytes[i..].starts_with(b"*/") {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    self.state = State::Code;
+                    return i;
+                }
+            } else {
+                i += 1;
+            }
+        }
+        self.state = State::BlockComment(depth);
+        i
+    }
+
+    // the same for a string, from just past its opening quote
+    fn string(&mut self, bytes: &[u8], mut i: usize) -> usize {
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => i += 2,
+                b'"' => {
+                    self.state = State::Code;
+                    return i + 1;
+                }
+                _ => i += 1,
+            }
+        }
+        // a \ at the very end escapes the newline, the string still goes on
+        self.state = State::Str;
+        bytes.len()
+    }
+
+    // and a raw string, where nothing is escaped and only " plus the right number of #s closes it
+    fn raw_string(&mut self, bytes: &[u8], mut i: usize, hashes: usize) -> usize {
+        while i < bytes.len() {
+            if bytes[i] == b'"'
+                && bytes[i + 1..].iter().take_while(|&&b| b == b'#').count() >= hashes
+            {
+                self.state = State::Code;
+                return i + 1 + hashes;
+            }
+            i += 1;
+        }
+        self.state = State::RawStr(hashes);
+        i
+    }
+}
+
+// The length of "...", b"... or c"... up to and including the opening quote.
+fn string_start(rest: &[u8]) -> Option<usize> {
+    match rest {
+        [b'"', ..] => Some(1),
+        [b'b' | b'c', b'"', ..] => Some(2),
+        _ => None,
+    }
+}
+
+// The same for r"...", r#"..."#, br"..." and cr"...", with the number of #s.
+fn raw_string_start(rest: &[u8]) -> Option<(usize, usize)> {
+    let prefix = match rest {
+        [b'r', ..] => 1,
+        [b'b' | b'c', b'r', ..] => 2,
+        _ => return None,
+    };
+    let hashes = rest[prefix..].iter().take_while(|&&b| b == b'#').count();
+    (rest.get(prefix + hashes) == Some(&b'"')).then_some((prefix + hashes + 1, hashes))
+}
+
+// skips a char literal or a lifetime starting at the ' at `i`
+fn char_or_lifetime(bytes: &[u8], i: usize) -> usize {
+    let rest = &bytes[i + 1..];
+    if let Some(escaped) = rest.strip_prefix(b"\\") {
+        // '\n', '\'', '\u{1F600}': the escaped char itself can be a quote, the next one ends it
+        return match escaped.iter().skip(1).position(|&b| b == b'\'') {
+            Some(end) => i + 3 + end + 1,
+            None => bytes.len(),
+        };
+    }
+    let mut chars = utf8::char_indices(rest);
+    match (chars.next(), chars.next()) {
+        (Some((first, _)), Some((_, '\''))) => i + 1 + first.end + 1,
+        // a lifetime, or a label: the name after it isn't an identifier to search
+        (Some((first, c)), _) if is_ident_start(c) => {
+            i + 1 + first.end + ident_len(&rest[first.end..])
+        }
+        _ => i + 1,
+    }
+}
+
+// 42, 0xff, 1_000u64, 1.5e3: digits and whatever letters follow, so a suffix isn't taken for an identifier
+fn number(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() {
+        let b = bytes[i];
+        let fraction = b == b'.' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit);
+        if !(b.is_ascii_alphanumeric() || b == b'_' || fraction) {
+            break;
+        }
+        i += 1;
+    }
+    i
+}
+
+fn is_ident_start(c: char) -> bool {
+    c == '_' || c.is_alphabetic()
+}
+
+fn ident_len(rest: &[u8]) -> usize {
+    utf8::char_indices(rest)
+        .find(|&(_, c)| !(c == '_' || c.is_alphanumeric()))
+        .map_or(rest.len(), |(at, _)| at.start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the text of each part of each line that's one of `kinds`
+    fn parts(source: &str, kinds: &[Syntax]) -> Vec<String> {
+        let mut lexer = Lexer::default();
+        source
+            .lines()
+            .flat_map(|line| {
+                lexer
+                    .next_line(line.as_bytes(), kinds)
+                    .into_iter()
+                    .map(|range| line[range].to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    const SOURCE: &str = r###"// parse the config
+fn parse(config: &str) -> u8 { /* config /* nested */
+   still comment */ let s = "config \" str"; 'c'; '"'; 1_000u8
+}
+let raw = r#"a "config"
+   b"#; let r#type = b"x"; // done
+fn long<'a>(x: &'a str) {}
+"###;
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(
+            vec!["parse", "config", "str", "u8", "s", "raw", "r#type", "long", "x", "str"],
+            parts(SOURCE, &[Syntax::Identifier])
+        );
+    }
+
+    #[test]
+    fn strings_across_lines() {
+        assert_eq!(
+            vec![
+                r#""config \" str""#,
+                r##"r#"a "config""##,
+                r##"   b"#"##,
+                r#"b"x""#
+            ],
+            parts(SOURCE, &[Syntax::String])
+        );
+    }
+
+    #[test]
+    fn byte_literals() {
+        let source = r####"let (q, x) = (b'\'', b'x'); let raw = br"\d+ bx"; let hashed = br#"say "hi""#;"####;
+
+        assert_eq!(
+            vec!["q", "x", "raw", "hashed"],
+            parts(source, &[Syntax::Identifier])
+        );
+        assert_eq!(
+            vec![r#"br"\d+ bx""#, r##"br#"say "hi""#"##],
+            parts(source, &[Syntax::String])
+        );
+        // b'"' doesn't open a string that swallows the rest of the line
+        assert_eq!(
+            vec!["after"],
+            parts(r#"b'"'; after"#, &[Syntax::Identifier])
+        );
+    }
+
+    #[test]
+    fn nested_block_comments() {
+        assert_eq!(
+            vec![
+                "// parse the config",
+                "/* config /* nested */",
+                "   still comment */",
+                "// done"
+            ],
+            parts(SOURCE, &[Syntax::Comment])
+        );
+    }
+}
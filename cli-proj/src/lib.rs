@@ -1,11 +1,92 @@
 use std::env;
 use std::error::Error;
-use std::fs;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use regex::bytes::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+
+mod args;
+mod color;
+mod decompress;
+mod filter;
+mod fold;
+mod fold_table;
+mod follow;
+mod fuzzy;
+mod index;
+mod json;
+mod lexer;
+mod matcher;
+mod multi;
+mod parallel;
+mod printer;
+mod replace;
+mod searcher;
+mod stream;
+mod utf8;
+mod walk;
+
+use crate::args::Opt;
+pub use crate::args::{usage, ParseError};
+pub use crate::color::ColorChoice;
+pub use crate::filter::Filter;
+use crate::fuzzy::FuzzyQuery;
+use crate::index::Index;
+pub use crate::lexer::Syntax;
+pub use crate::matcher::{Boundary, Match, Matcher};
+use crate::multi::MultiPattern;
+pub use crate::printer::{OutputMode, Printer, Stats};
+pub use crate::searcher::{SearchError, Searcher, SearcherBuilder, Sink};
+pub use crate::stream::search_reader;
 
 pub struct Config {
-    pub filename: String,
-    pub query: String,
+    // files and/or directories to search, directories are searched recursively
+    pub paths: Vec<String>,
+    // what to search for: the QUERY, or every -e PATTERN and line of a -f FILE in the order given
+    pub patterns: Vec<String>,
     pub case_sensitive: bool,
+    // Some when the patterns should be treated as regular expressions (-E / --regex).
+    // The pattern is compiled once here so a bad pattern is reported before any file is read.
+    // With several patterns it's one alternation of all of them, plus a set to tell them apart.
+    pub regex: Option<Regex>,
+    pub regex_set: Option<RegexSet>,
+    // --show-pattern: print which patterns were found on each line
+    pub show_pattern: bool,
+    // --text: search binary files as if they were text, printing their lines
+    pub text: bool,
+    // --no-index turns this off: skip files a directory's trigram index rules out (see index.rs)
+    pub use_index: bool,
+    // --follow/--watch: keep searching the files as they grow, and directories for new files (see follow.rs)
+    pub follow: bool,
+    // -w/-x: hits have to be whole words or whole lines
+    pub boundary: Option<Boundary>,
+    // --only: treat files as Rust source and only count hits inside these (see lexer.rs), empty for anywhere
+    pub only: Vec<Syntax>,
+    // --fuzzy: how many typos a match may have, and --sort-distance to print the closest lines first
+    pub fuzzy: Option<usize>,
+    pub sort_distance: bool,
+    // --column: print every match as path:line:col:text
+    pub column: bool,
+    // -B/-A (or -C for both): how many lines to show before/after each match, like grep
+    pub before_context: usize,
+    pub after_context: usize,
+    // -j/--threads: how many files to search at once, defaults to the number of CPUs
+    pub threads: usize,
+    // -v: select the lines that don't match instead
+    pub invert: bool,
+    // -c/-l/-L print a summary per file instead of the selected lines
+    pub mode: OutputMode,
+    // --json: one JSON object per line (begin/match/context/end per file, then a summary) instead of text
+    pub json: bool,
+    // --color=auto|always|never, already resolved against the terminal and NO_COLOR
+    pub color: bool,
+    // which files to search while walking directories (--glob, --exclude, --no-ignore and ignore files)
+    pub filter: Filter,
+    // --replace: show (or with --write, make) this replacement for every match instead of printing matches
+    pub replace: Option<String>,
+    pub write: bool,
 }
 
 impl Config {
@@ -33,49 +114,565 @@ impl Config {
     // now we’re passing ownership of the iterator returned from env::args to Config::new directly.
     // Because we’re taking ownership of args and we’ll be mutating args by iterating over it,
     // we can add the mut keyword into the specification of the args parameter to make it mutable.
-    pub fn new(mut args: env::Args) -> Result<Config, &'static str> {
-        if args.len() < 3 {
-            return Err("not enough arguments!");
-        }
+    // Any iterator of Strings works (not just env::Args), which lets the tests build a Config too.
+    pub fn new(mut args: impl Iterator<Item = String>) -> Result<Config, ParseError> {
         // we can now manually iterate over our args since it implements an Iterator
         args.next();
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("no query provided!"),
-        };
-        let filename = match args.next() {
-            Some(arg) => arg,
-            None => return Err("no filename provided!"),
-        };
-        let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
+        // the flags can appear anywhere, everything else is positional (query, then one or more paths)
+        let parsed = args::parse(args)?;
+
+        // the env var is only the default, -i and -s on the command line win over it
+        let mut case_sensitive = env::var("CASE_INSENSITIVE").is_err();
+        let mut use_regex = false;
+        let mut column = false;
+        let mut before_context = 0;
+        let mut after_context = 0;
+        let mut threads = parallel::default_threads();
+        let mut invert = false;
+        let mut mode = OutputMode::Lines;
+        let mut json = false;
+        // which of -c/-l/-L set the mode, for the error message if it's combined with --json
+        let mut mode_option = "";
+        let mut color = ColorChoice::Auto;
+        let mut globs = Vec::new();
+        let mut excludes = Vec::new();
+        let mut respect_ignore = true;
+        let mut replace = None;
+        let mut write = false;
+        let mut patterns = Vec::new();
+        let mut show_pattern = false;
+        let mut boundary = None;
+        let mut text = false;
+        let mut use_index = true;
+        let mut follow = false;
+        let mut only = Vec::new();
+        let mut fuzzy = None;
+        let mut sort_distance = false;
+        for (opt, value) in parsed.options {
+            match opt.long {
+                "regex" => use_regex = true,
+                "pattern" => patterns.extend(value),
+                "file" => patterns.extend(read_patterns(&value.unwrap_or_default())?),
+                "show-pattern" => show_pattern = true,
+                // like grep, -x wins over -w whichever comes first
+                "word-regexp" => boundary = boundary.or(Some(Boundary::Word)),
+                "line-regexp" => boundary = Some(Boundary::Line),
+                "text" => text = true,
+                "no-index" => use_index = false,
+                "follow" | "watch" => follow = true,
+                // --only string,comment or --only string --only comment
+                "only" => {
+                    for name in value.unwrap_or_default().split(',') {
+                        only.push(Syntax::parse(name).ok_or(ParseError::InvalidChoice {
+                            option: "--only".to_string(),
+                            value: name.to_string(),
+                            choices: "identifier, string, comment",
+                        })?);
+                    }
+                }
+                "fuzzy" => fuzzy = Some(number(opt, value)?),
+                "sort-distance" => sort_distance = true,
+                "ignore-case" => case_sensitive = false,
+                "case-sensitive" => case_sensitive = true,
+                "column" => column = true,
+                "after-context" => after_context = number(opt, value)?,
+                "before-context" => before_context = number(opt, value)?,
+                "context" => {
+                    after_context = number(opt, value)?;
+                    before_context = after_context;
+                }
+                "threads" => {
+                    threads = number(opt, value)?;
+                    if threads == 0 {
+                        return Err(ParseError::InvalidNumber {
+                            option: "--threads".to_string(),
+                            value: "0".to_string(),
+                        });
+                    }
+                }
+                "invert-match" => invert = true,
+                "count" | "files-with-matches" | "files-without-match" => {
+                    mode = match opt.long {
+                        "count" => OutputMode::Count,
+                        "files-with-matches" => OutputMode::FilesWithMatches,
+                        _ => OutputMode::FilesWithoutMatch,
+                    };
+                    mode_option = opt.long;
+                }
+                "json" => json = true,
+                "color" => {
+                    let value = value.unwrap_or_default();
+                    color = ColorChoice::parse(&value).ok_or(ParseError::InvalidChoice {
+                        option: "--color".to_string(),
+                        value,
+                        choices: "auto, always, never",
+                    })?;
+                }
+                // these can be given more than once, i.e. -g '*.rs' -g '*.toml'
+                "glob" => globs.extend(value),
+                "exclude" => excludes.extend(value),
+                "no-ignore" => respect_ignore = false,
+                "replace" => replace = value,
+                "write" => write = true,
+                "help" => return Err(ParseError::HelpRequested),
+                "version" => return Err(ParseError::VersionRequested),
+                _ => unreachable!("--{} is in args::OPTIONS but not handled here", opt.long),
+            }
+        }
+        // the JSON records describe lines, there's no JSON form of -c/-l/-L
+        if json && mode != OutputMode::Lines {
+            return Err(ParseError::ConflictingOptions(
+                "--json".to_string(),
+                format!("--{}", mode_option),
+            ));
+        }
+        if write && replace.is_none() {
+            return Err(ParseError::Requires(
+                "--write".to_string(),
+                "--replace".to_string(),
+            ));
+        }
+        // the output of a replacement is a diff (or a list of the files written), not matching lines,
+        // so there's nothing for the options about how matches are shown to change
+        if replace.is_some() {
+            let conflict = if invert {
+                Some("--invert-match".to_string())
+            } else if mode != OutputMode::Lines {
+                Some(format!("--{}", mode_option))
+            } else if json {
+                Some("--json".to_string())
+            } else if column {
+                Some("--column".to_string())
+            } else {
+                None
+            };
+            if let Some(other) = conflict {
+                return Err(ParseError::ConflictingOptions(
+                    "--replace".to_string(),
+                    other,
+                ));
+            }
+        }
+        if sort_distance && fuzzy.is_none() {
+            return Err(ParseError::Requires(
+                "--sort-distance".to_string(),
+                "--fuzzy".to_string(),
+            ));
+        }
+        // context is printed in file order, which sorted lines no longer are
+        if sort_distance && (before_context > 0 || after_context > 0) {
+            return Err(ParseError::ConflictingOptions(
+                "--sort-distance".to_string(),
+                "--context".to_string(),
+            ));
+        }
+        // following prints each line as it's found, there's no end of the file to count or sort at,
+        // and no way of knowing which lines will come after a match
+        if follow {
+            let conflict = if mode != OutputMode::Lines {
+                Some(format!("--{}", mode_option))
+            } else if replace.is_some() {
+                Some("--replace".to_string())
+            } else if sort_distance {
+                Some("--sort-distance".to_string())
+            } else if before_context > 0 || after_context > 0 {
+                Some("--context".to_string())
+            } else if json {
+                Some("--json".to_string())
+            } else {
+                None
+            };
+            if let Some(other) = conflict {
+                return Err(ParseError::ConflictingOptions(
+                    "--follow".to_string(),
+                    other,
+                ));
+            }
+        }
+        // a lexed line is only searched inside the chosen parts, there's no "outside" of them to select
+        // or rewrite, and following a file doesn't keep track of where its comments and strings are
+        if !only.is_empty() {
+            let conflict = if invert {
+                Some("--invert-match")
+            } else if replace.is_some() {
+                Some("--replace")
+            } else if follow {
+                Some("--follow")
+            } else {
+                None
+            };
+            if let Some(other) = conflict {
+                return Err(ParseError::ConflictingOptions(
+                    "--only".to_string(),
+                    other.to_string(),
+                ));
+            }
+        }
+        let mut positional = parsed.positional.into_iter();
+
+        // without -e/-f the first positional is the query, with them every positional is a path
+        if patterns.is_empty() {
+            match positional.next() {
+                Some(arg) => patterns.push(arg),
+                None => return Err(ParseError::MissingQuery),
+            }
+        }
+        let paths: Vec<String> = positional.collect();
+        if paths.is_empty() {
+            return Err(ParseError::MissingPath);
+        }
+        // stdin has nowhere to be written back to
+        if write && paths.iter().any(|p| p == "-") {
+            return Err(ParseError::ConflictingOptions(
+                "--write".to_string(),
+                "- (stdin)".to_string(),
+            ));
+        }
+
+        // stdin is already a stream, there's nothing to reopen (`tail -f log | cli-proj QUERY -` follows it)
+        if follow && paths.iter().any(|p| p == "-") {
+            return Err(ParseError::ConflictingOptions(
+                "--follow".to_string(),
+                "- (stdin)".to_string(),
+            ));
+        }
+
+        let (regex, regex_set) =
+            compile_patterns(&patterns, use_regex, case_sensitive, boundary, fuzzy)?;
 
         Ok(Config {
-            filename,
-            query,
+            paths,
+            patterns,
             case_sensitive,
+            regex,
+            regex_set,
+            show_pattern,
+            boundary,
+            text,
+            use_index,
+            follow,
+            only,
+            fuzzy,
+            sort_distance,
+            column,
+            before_context,
+            after_context,
+            threads,
+            invert,
+            mode,
+            json,
+            // JSON is for programs, escape codes in it would only get in the way
+            color: !json && color.resolve(),
+            filter: Filter::new(&globs, &excludes, respect_ignore)
+                .map_err(ParseError::InvalidGlob)?,
+            replace,
+            write,
         })
     }
+
+    pub fn matcher(&self) -> Matcher {
+        let matcher = match (&self.regex, &self.regex_set, self.fuzzy) {
+            // Regex is reference counted internally, so this clone is cheap
+            (Some(re), Some(set), _) => Matcher::RegexSet(re.clone(), set.clone()),
+            (Some(re), None, _) => Matcher::Regex(re.clone()),
+            (None, _, Some(max_distance)) => Matcher::Fuzzy(FuzzyQuery::new(
+                &self.patterns[0],
+                max_distance,
+                self.case_sensitive,
+            )),
+            _ if self.patterns.len() == 1 => {
+                Matcher::literal(&self.patterns[0], self.case_sensitive)
+            }
+            _ => Matcher::Patterns(MultiPattern::new(&self.patterns, self.case_sensitive)),
+        };
+        // the regexes already have the boundary built in
+        let matcher = match self.boundary {
+            Some(boundary) if self.regex.is_none() => Matcher::Bounded(Box::new(matcher), boundary),
+            _ => matcher,
+        };
+        let matcher = if self.only.is_empty() {
+            matcher
+        } else {
+            Matcher::Within(Box::new(matcher), self.only.clone())
+        };
+        if self.invert {
+            Matcher::Inverted(Box::new(matcher))
+        } else {
+            matcher
+        }
+    }
+
+    pub fn printer<W: Write>(&self, out: W) -> Printer<W> {
+        Printer::new(out, self.column, self.before_context, self.after_context)
+            .with_mode(self.mode)
+            .with_json(self.json)
+            .with_color(self.color)
+            .with_patterns(&self.patterns, self.show_pattern)
+            .with_ranking(self.sort_distance)
+            .with_text(self.text)
+    }
 }
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    // '?' (Ch.8) will return the Error of the Result
-    let contents = fs::read_to_string(config.filename)?;
+// -f FILE: one pattern per line. Like grep, an empty line is an (empty) pattern that matches everything.
+fn read_patterns(path: &str) -> Result<Vec<String>, ParseError> {
+    let contents = fs::read_to_string(path).map_err(|e| ParseError::PatternFile {
+        path: path.to_string(),
+        error: e.to_string(),
+    })?;
+    Ok(lines_with_offsets(&contents)
+        .map(|(_, line)| line.to_string())
+        .collect())
+}
 
-    let results = if config.case_sensitive {
-        search(&config.query, &contents)
-    } else {
-        search_case_insensitive(&config.query, &contents)
+// Checks the patterns against the other settings and, with -E, compiles them. Shared by Config::new and
+// the library's SearcherBuilder (see searcher.rs), so both turn down the same combinations.
+pub(crate) fn compile_patterns(
+    patterns: &[String],
+    use_regex: bool,
+    case_sensitive: bool,
+    boundary: Option<Boundary>,
+    fuzzy: Option<usize>,
+) -> Result<(Option<Regex>, Option<RegexSet>), ParseError> {
+    if fuzzy.is_some() {
+        // a fuzzy hit is the closest part of the line, not the closest alignment with all of it
+        let conflict = if boundary == Some(Boundary::Line) {
+            Some("--line-regexp")
+        } else if use_regex {
+            Some("--regex")
+        } else if patterns.len() > 1 {
+            Some("more than one pattern")
+        } else {
+            None
+        };
+        if let Some(other) = conflict {
+            return Err(ParseError::ConflictingOptions(
+                "--fuzzy".to_string(),
+                other.to_string(),
+            ));
+        }
+    }
+    let bounded = |pattern: &str| match boundary {
+        Some(boundary) => boundary.wrap_regex(pattern),
+        None => pattern.to_string(),
     };
+    Ok(if !use_regex {
+        (None, None)
+    } else if patterns.len() == 1 {
+        (
+            Some(build_regex(&bounded(&patterns[0]), case_sensitive)?),
+            None,
+        )
+    } else {
+        // non-capturing groups keep each pattern's own | to itself and its groups numbered as written
+        let alternation: Vec<String> = patterns.iter().map(|p| format!("(?:{})", p)).collect();
+        let set = RegexSetBuilder::new(patterns.iter().map(|p| bounded(p)))
+            .case_insensitive(!case_sensitive)
+            .build()
+            .map_err(ParseError::InvalidRegex)?;
+        (
+            Some(build_regex(
+                &bounded(&alternation.join("|")),
+                case_sensitive,
+            )?),
+            Some(set),
+        )
+    })
+}
+
+// Compile the query once, folding case in the regex engine itself rather than lowercasing every line.
+fn build_regex(query: &str, case_sensitive: bool) -> Result<Regex, ParseError> {
+    RegexBuilder::new(query)
+        .case_insensitive(!case_sensitive)
+        .build()
+        .map_err(ParseError::InvalidRegex)
+}
+
+// the value of an option that takes a count, like -A 3 (the parser already made sure there is one)
+fn number(opt: &Opt, value: Option<String>) -> Result<usize, ParseError> {
+    let value = value.unwrap_or_default();
+    value.parse().map_err(|_| ParseError::InvalidNumber {
+        option: format!("--{}", opt.long),
+        value,
+    })
+}
+
+// Returns whether anything was selected (a matching line, or with -L a file without one),
+// which main turns into grep's exit status: 0 if something was, 1 if nothing was, 2 for an error.
+pub fn run(config: Config) -> Result<bool, Box<dyn Error>> {
+    // '?' (Ch.8) will return the Error of the Result
+    // like grep -r, only say which file a line came from once there's more than one candidate
+    let show_path = config.paths.len() > 1 || config.paths.iter().any(|p| Path::new(p).is_dir());
+    if config.follow {
+        // this only comes back if something goes wrong, otherwise it runs until it's interrupted
+        follow::follow(&config, show_path || config.column, io::stdout().lock())?;
+        return Ok(true);
+    }
+    let files = rust_files(&config, walk::collect_files(&config.paths, &config.filter)?);
+    let selected = search_files(&config, &files, show_path, io::stdout().lock())?;
+    Ok(selected)
+}
+
+// --only understands Rust and nothing else, so of the files found in directories only the .rs ones
+// are searched. A file named on the command line (stdin too) is taken to be Rust whatever it's called.
+fn rust_files(config: &Config, mut files: Vec<PathBuf>) -> Vec<PathBuf> {
+    if !config.only.is_empty() {
+        let named: Vec<&Path> = config
+            .paths
+            .iter()
+            .map(Path::new)
+            .filter(|path| !path.is_dir())
+            .collect();
+        files.retain(|file| {
+            named.contains(&file.as_path()) || file.extension().is_some_and(|ext| ext == "rs")
+        });
+    }
+    files
+}
+
+// `cli-proj index DIR...` is a command of its own rather than a search, but only with "index" straight
+// after the program name: anywhere else (`-e index`, `-- index`) it's a word to search for like any other.
+pub fn is_index_command(args: &[String]) -> bool {
+    args.get(1).is_some_and(|arg| arg == "index")
+}
 
-    for line in results {
-        println!("{}", line)
+// `cli-proj index DIR...`: builds or refreshes the trigram index of each directory (see index.rs).
+// `args` are the ones after "index".
+pub fn run_index(args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let dirs: Vec<String> = args.collect();
+    if dirs.is_empty() {
+        return Err(Box::new(ParseError::MissingPath));
+    }
+    for dir in dirs {
+        let summary = Index::update(Path::new(&dir))?;
+        println!(
+            "{}: {} files indexed ({} read, {} removed, {} left out as just modified)",
+            dir, summary.files, summary.read, summary.removed, summary.racy
+        );
     }
-    // but using () like this is the idiomatic way to indicate that we’re calling
-    // run for its side effects only; it doesn’t return a value we need.
     Ok(())
 }
 
+// The part of run after the file list is known, writing to any output so the tests can check it.
+fn search_files<W: Write>(
+    config: &Config,
+    files: &[PathBuf],
+    show_path: bool,
+    mut out: W,
+) -> io::Result<bool> {
+    // a file the index rules out counts as searched, it's known to have nothing in it
+    let (files, ruled_out) = index::narrow(config, files.to_vec())?;
+    let files = &files[..];
+    if let Some(replacement) = &config.replace {
+        return replace::replace_files(config, replacement, files, out);
+    }
+    let matcher = config.matcher();
+    // --column always names the file, an editor can't jump anywhere without it
+    let show_path = show_path || config.column;
+
+    // stdin can only be read once, by one thread, so it always goes down the single-threaded path
+    let parallel =
+        config.threads > 1 && files.len() > 1 && !files.iter().any(|f| f == Path::new("-"));
+    if !parallel {
+        let mut printer = config.printer(out).with_paths(show_path);
+        let mut selected = false;
+        let mut unreadable = 0;
+        for file in files {
+            match search_path(&matcher, file, &mut printer) {
+                Ok(file_selected) => selected |= file_selected,
+                Err(e) => unreadable += skip_unreadable(file, e)?,
+            }
+        }
+        let mut stats = printer.stats();
+        stats.files_searched += ruled_out;
+        printer.print_summary(stats)?;
+        return searched_all(selected, unreadable);
+    }
+
+    // each worker prints one whole file into its own buffer, and the buffers are written out in file order
+    let has_context =
+        config.mode == OutputMode::Lines && (config.before_context > 0 || config.after_context > 0);
+    let mut printed_any = false;
+    let mut selected = false;
+    let mut unreadable = 0;
+    let mut stats = Stats::default();
+    parallel::search_in_parallel(
+        files,
+        config.threads,
+        |file| {
+            // a file that can't be read comes back with its error, to be reported in file order below
+            let mut printer = config.printer(Vec::new()).with_paths(show_path);
+            let result = search_path(&matcher, file, &mut printer);
+            let stats = printer.stats();
+            Ok::<_, io::Error>((printer.into_inner(), result, stats))
+        },
+        |file, (buf, result, file_stats): (Vec<u8>, io::Result<bool>, Stats)| {
+            stats += file_stats;
+            if !buf.is_empty() {
+                // every buffer came from a fresh Printer, so the `--` between files has to be added here
+                if printed_any && has_context && !config.json {
+                    writeln!(out, "--")?;
+                }
+                printed_any = true;
+                out.write_all(&buf)?;
+            }
+            match result {
+                Ok(file_selected) => selected |= file_selected,
+                Err(e) => unreadable += skip_unreadable(file, e)?,
+            }
+            Ok(())
+        },
+    )?;
+    stats.files_searched += ruled_out;
+    config.printer(&mut out).print_summary(stats)?;
+    searched_all(selected, unreadable)
+}
+
+// Like grep, a file that can't be read (no permission, deleted since the walk, a corrupt gzip) is
+// reported as `path: error` and skipped, and the rest are still searched. It counts 1 towards the
+// unreadable files. Not being able to write the output is different: that still ends the run.
+fn skip_unreadable(file: &Path, e: io::Error) -> io::Result<usize> {
+    if e.kind() == io::ErrorKind::BrokenPipe {
+        return Err(e);
+    }
+    eprintln!("{}: {}", file.display(), e);
+    Ok(1)
+}
+
+// Every file was searched by now, but if some couldn't be read the run is an error (exit status 2)
+// whatever was selected in the others, the same as grep. Each file was already reported on its own.
+fn searched_all(selected: bool, unreadable: usize) -> io::Result<bool> {
+    match unreadable {
+        0 => Ok(selected),
+        1 => Err(io::Error::other("1 file couldn't be read")),
+        n => Err(io::Error::other(format!("{} files couldn't be read", n))),
+    }
+}
+
+// Searches a single file (or stdin) and prints whatever the output mode asks for, returning whether it was a hit.
+fn search_path<W: Write>(
+    matcher: &Matcher,
+    file: &Path,
+    printer: &mut Printer<W>,
+) -> io::Result<bool> {
+    // files are read through a BufReader and searched a line at a time (see stream.rs),
+    // so a multi-gigabyte log never has to fit in memory
+    // compressed files (and compressed stdin) are searched through a decoder, see decompress.rs
+    if file == Path::new("-") {
+        let label = Path::new("(standard input)");
+        let mut reader = decompress::reader(io::stdin().lock())?;
+        printer.start_file(reader.fill_buf()?);
+        let count = search_reader(matcher, reader, printer, label)?;
+        return printer.finish_file(label, count);
+    }
+
+    let mut reader = decompress::reader(BufReader::new(File::open(file)?))?;
+    // the first chunk of the file is enough to spot a binary file, without reading the rest
+    printer.start_file(reader.fill_buf()?);
+    let count = search_reader(matcher, reader, printer, file)?;
+    printer.finish_file(file, count)
+}
+
 // tell Rust that the data returned by the search function will live
 // as long as the data passed into the search function in the contents argument.
 /*
@@ -85,35 +682,115 @@ Rust can’t possibly know which of the two arguments we need, so we need to tel
    to the return value using the lifetime syntax. (see lifetimes examples)
 */
 pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    // note the functional approach here opposed to the original used below in the case insensitive version
+    lines_of(
+        contents,
+        search_matches(&Matcher::literal(query, true), contents),
+    )
+}
+
+pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    lines_of(
+        contents,
+        search_matches(&Matcher::literal(query, false), contents),
+    )
+}
+
+// Same shape as search, but each line is tested against a compiled Regex instead of a literal substring.
+// Case-insensitivity is baked into the Regex (see build_regex), so there is no separate insensitive version.
+pub fn search_regex<'a>(re: &Regex, contents: &'a str) -> Vec<&'a str> {
+    lines_of(
+        contents,
+        search_matches(&Matcher::Regex(re.clone()), contents),
+    )
+}
+
+// Lines with something no more than max_distance typos away from the query (see fuzzy.rs).
+pub fn search_fuzzy<'a>(query: &str, max_distance: usize, contents: &'a str) -> Vec<&'a str> {
+    let matcher = Matcher::Fuzzy(FuzzyQuery::new(query, max_distance, true));
+    lines_of(contents, search_matches(&matcher, contents))
+}
+
+// The search functions above are thin wrappers around this one: it does the same filtering,
+// but keeps where each line is (line number, byte offset) and where the hits are inside it.
+pub fn search_matches<'a>(matcher: &Matcher, contents: &'a str) -> Vec<Match<'a>> {
+    // note the functional approach here opposed to a loop pushing into a results vector
     /*
     lets us avoid having a mutable intermediate results vector. The functional programming style prefers to
     minimize the amount of mutable state to make code clearer. Removing the mutable state might enable a
     future enhancement to make searching happen in parallel, because we wouldn’t have to manage
     concurrent access to the results vector.
     */
-    contents
-        .lines()
-        .filter(|line| line.contains(query))
+    lines_with_offsets(contents)
+        .enumerate()
+        .map(|(index, (byte_offset, line))| (index, byte_offset, line.as_bytes()))
+        .filter(|&(_, _, line)| matcher.is_match(line))
+        .map(|(index, byte_offset, line)| Match {
+            line_number: index + 1,
+            byte_offset,
+            columns: matcher.find_all(line),
+            patterns: matcher.patterns_in(line),
+            distance: matcher.distance(line),
+            line,
+        })
         .collect()
 }
 
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
-    let mut results = Vec::new();
+// Turns each match into the window of lines to print around it (0-based line indexes),
+// merging windows that overlap or touch so no line is printed twice and `--` only goes between real gaps.
+pub fn context_groups(
+    matches: &[Match],
+    total_lines: usize,
+    before: usize,
+    after: usize,
+) -> Vec<Range<usize>> {
+    merge_windows(
+        matches.iter().map(|m| m.line_number - 1),
+        total_lines,
+        before,
+        after,
+    )
+}
 
-    for line in contents.lines() {
-        // pass our Heap query String as a 'slice' as specified by contains method
-        if line.to_lowercase().contains(&query) {
-            results.push(line);
+// context_groups for any sorted line indexes (the diff hunks of --replace are built the same way)
+pub(crate) fn merge_windows(
+    indexes: impl Iterator<Item = usize>,
+    total_lines: usize,
+    before: usize,
+    after: usize,
+) -> Vec<Range<usize>> {
+    let mut groups: Vec<Range<usize>> = Vec::new();
+    for index in indexes {
+        let window = index.saturating_sub(before)..(index + after + 1).min(total_lines);
+        match groups.last_mut() {
+            Some(last) if window.start <= last.end => last.end = last.end.max(window.end),
+            _ => groups.push(window),
         }
     }
-    results
+    groups
+}
+
+// the matching lines, as the slices of `contents` they are
+fn lines_of<'a>(contents: &'a str, matches: Vec<Match>) -> Vec<&'a str> {
+    matches
+        .into_iter()
+        .map(|m| &contents[m.byte_offset..m.byte_offset + m.line.len()])
+        .collect()
+}
+
+// Like str::lines (a trailing "\n" or "\r\n" is not part of the line), but also yields where each line starts.
+pub(crate) fn lines_with_offsets(contents: &str) -> impl Iterator<Item = (usize, &str)> {
+    contents.split_inclusive('\n').scan(0, |offset, raw| {
+        let start = *offset;
+        *offset += raw.len();
+        let line = raw.strip_suffix('\n').unwrap_or(raw);
+        Some((start, line.strip_suffix('\r').unwrap_or(line)))
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
 
     #[test]
     fn case_sensitive() {
@@ -140,4 +817,948 @@ trust me.";
             search_case_insensitive(query, contents)
         );
     }
+
+    #[test]
+    fn case_insensitive_unicode() {
+        let query = "ЗДРАВСТВУЙТЕ";
+        let contents = "\
+Здравствуйте, мир
+Hello, world
+здравствуйте!";
+
+        assert_eq!(
+            vec!["Здравствуйте, мир", "здравствуйте!"],
+            search_case_insensitive(query, contents)
+        );
+        assert_eq!(
+            vec!["Die Straße"],
+            search_case_insensitive("strasse", "Die Straße\nDie Strasze")
+        );
+    }
+
+    #[test]
+    fn regex_search() {
+        let re = build_regex(r"fn \w+\(", true).unwrap();
+        let contents = "\
+fn main() {
+    // fn is a keyword
+    helper();
+}
+fn helper() {}";
+
+        assert_eq!(
+            vec!["fn main() {", "fn helper() {}"],
+            search_regex(&re, contents)
+        );
+    }
+
+    #[test]
+    fn regex_anchored() {
+        let re = build_regex("^Rust", true).unwrap();
+        let contents = "\
+Rust:
+trust me, Rust.";
+
+        assert_eq!(vec!["Rust:"], search_regex(&re, contents));
+    }
+
+    #[test]
+    fn regex_case_insensitive() {
+        let re = build_regex("^r.st", false).unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+rest here.";
+
+        assert_eq!(vec!["Rust:", "rest here."], search_regex(&re, contents));
+    }
+
+    #[test]
+    fn whole_word_and_line() {
+        let contents = "\
+Rust:
+safe, fast, productive.
+duct tape
+rust";
+        let word = |query, case_sensitive| {
+            let matcher = Matcher::Bounded(
+                Box::new(Matcher::literal(query, case_sensitive)),
+                Boundary::Word,
+            );
+            lines_of(contents, search_matches(&matcher, contents))
+        };
+        let line = |query, case_sensitive| {
+            let matcher = Matcher::Bounded(
+                Box::new(Matcher::literal(query, case_sensitive)),
+                Boundary::Line,
+            );
+            lines_of(contents, search_matches(&matcher, contents))
+        };
+
+        // unlike search, "duct" no longer finds "productive"
+        assert_eq!(vec!["duct tape"], word("duct", true));
+        assert_eq!(vec!["duct tape"], word("DUCT", false));
+        assert_eq!(vec!["Rust:", "rust"], word("rust", false));
+        assert_eq!(vec!["rust"], line("rust", true));
+        assert_eq!(vec!["rust"], line("RUST", false));
+    }
+
+    #[test]
+    fn fuzzy_search() {
+        let contents = "\
+connection recieved
+connection received
+conection reset
+nothing here";
+
+        assert_eq!(
+            vec!["connection received"],
+            search_fuzzy("received", 0, contents)
+        );
+        assert_eq!(
+            vec!["connection recieved", "connection received"],
+            search_fuzzy("received", 2, contents)
+        );
+        assert_eq!(
+            vec![
+                "connection recieved",
+                "connection received",
+                "conection reset"
+            ],
+            search_fuzzy("connection", 1, contents)
+        );
+    }
+
+    #[test]
+    fn config_regex_flag() {
+        let args = ["cli-proj", "-E", "^fn", "file.txt"]
+            .iter()
+            .map(|s| s.to_string());
+        let config = Config::new(args).unwrap();
+
+        assert_eq!(vec!["^fn"], config.patterns);
+        assert_eq!(vec!["file.txt"], config.paths);
+        assert!(config.regex.is_some());
+    }
+
+    #[test]
+    fn match_records() {
+        let contents = "\
+Rust:
+safe, fast, productive.\r
+Pick three, or reduce.";
+
+        assert_eq!(
+            vec![
+                Match {
+                    line_number: 2,
+                    byte_offset: 6,
+                    columns: vec![Range { start: 15, end: 18 }],
+                    patterns: vec![0],
+                    distance: None,
+                    line: b"safe, fast, productive.",
+                },
+                Match {
+                    line_number: 3,
+                    byte_offset: 31,
+                    columns: vec![Range { start: 17, end: 20 }],
+                    patterns: vec![0],
+                    distance: None,
+                    line: b"Pick three, or reduce.",
+                },
+            ],
+            search_matches(&Matcher::literal("duc", true), contents)
+        );
+    }
+
+    #[test]
+    fn match_records_every_hit() {
+        let re = build_regex("t+", true).unwrap();
+        let matches = search_matches(&Matcher::Regex(re), "trust test");
+
+        assert_eq!(vec![0..1, 4..5, 6..7, 9..10], matches[0].columns);
+    }
+
+    const POEM: &str = "\
+one
+two match
+three
+four
+five
+six match
+seven match
+eight
+nine
+ten match";
+
+    fn context_output(before: usize, after: usize, show_path: bool) -> String {
+        let matches = search_matches(&Matcher::literal("match", true), POEM);
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&mut out, false, before, after).with_paths(show_path);
+        printer
+            .print_file(Path::new("poem.txt"), POEM, &matches)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn context_after() {
+        assert_eq!(
+            "two match\nthree\n--\nsix match\nseven match\neight\n--\nten match\n",
+            context_output(0, 1, false)
+        );
+    }
+
+    #[test]
+    fn context_before() {
+        assert_eq!(
+            "one\ntwo match\n--\nfive\nsix match\nseven match\n--\nnine\nten match\n",
+            context_output(1, 0, false)
+        );
+    }
+
+    #[test]
+    fn context_overlapping_windows_merge() {
+        // the windows around six, seven and ten overlap or touch, so they print as one group
+        assert_eq!(
+            "one\ntwo match\nthree\n--\nfive\nsix match\nseven match\neight\nnine\nten match\n",
+            context_output(1, 1, false)
+        );
+    }
+
+    #[test]
+    fn context_clamped_at_start_and_end_of_file() {
+        let matches = search_matches(&Matcher::literal("match", true), POEM);
+
+        assert_eq!(vec![0..10], context_groups(&matches, 10, 5, 5));
+        assert_eq!(vec![0..2, 4..7, 8..10], context_groups(&matches, 10, 1, 0));
+    }
+
+    #[test]
+    fn context_lines_marked_with_dash() {
+        assert_eq!(
+            "poem.txt:two match\npoem.txt-three\n--\npoem.txt:six match\npoem.txt:seven match\npoem.txt-eight\n--\npoem.txt:ten match\n",
+            context_output(0, 1, true)
+        );
+    }
+
+    #[test]
+    fn config_context_flags() {
+        let args = ["cli-proj", "-C", "2", "-A", "3", "q", "f"]
+            .iter()
+            .map(|s| s.to_string());
+        let config = Config::new(args).unwrap();
+
+        assert_eq!((2, 3), (config.before_context, config.after_context));
+
+        let args = ["cli-proj", "-B", "lots", "q", "f"]
+            .iter()
+            .map(|s| s.to_string());
+        assert_eq!(
+            Err(ParseError::InvalidNumber {
+                option: "--before-context".to_string(),
+                value: "lots".to_string()
+            }),
+            Config::new(args).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn config_multiple_paths() {
+        let args = ["cli-proj", "stuff", "src", "file.txt"]
+            .iter()
+            .map(|s| s.to_string());
+        let config = Config::new(args).unwrap();
+
+        assert_eq!(vec!["stuff"], config.patterns);
+        assert_eq!(vec!["src", "file.txt"], config.paths);
+    }
+
+    fn run_mode(flags: &[&str], files: &[(&str, &str)]) -> (String, bool) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut args = vec!["cli-proj".to_string()];
+        args.extend(flags.iter().map(|s| s.to_string()));
+        for (name, contents) in files {
+            fs::write(dir.path().join(name), contents).unwrap();
+            args.push(dir.path().join(name).to_str().unwrap().to_string());
+        }
+        let config = Config::new(args.into_iter()).unwrap();
+        let files = walk::collect_files(&config.paths, &config.filter).unwrap();
+        let mut out = Vec::new();
+        let selected = search_files(&config, &files, files.len() > 1, &mut out).unwrap();
+        // keep only the file names, the temp dir is different every time
+        let out = String::from_utf8(out).unwrap();
+        let prefix = format!("{}/", dir.path().display());
+        (out.replace(&prefix, ""), selected)
+    }
+
+    const FILES: [(&str, &str); 3] = [
+        ("a.txt", "Rust:\nsafe, fast, productive.\nPick three.\n"),
+        ("b.txt", "nothing to see\n"),
+        ("c.txt", "productive\nproductive\n"),
+    ];
+
+    #[test]
+    fn invert_match() {
+        assert_eq!(
+            (
+                "a.txt:Rust:\na.txt:Pick three.\nb.txt:nothing to see\n".to_string(),
+                true
+            ),
+            run_mode(&["-v", "-j1", "duct"], &FILES)
+        );
+        assert_eq!(
+            (String::new(), false),
+            run_mode(&["--invert-match", "duct"], &FILES[2..])
+        );
+    }
+
+    #[test]
+    fn count_mode() {
+        assert_eq!(
+            ("a.txt:1\nb.txt:0\nc.txt:2\n".to_string(), true),
+            run_mode(&["-c", "duct"], &FILES)
+        );
+        // with a single file there's no path, just the number
+        assert_eq!(
+            ("0\n".to_string(), false),
+            run_mode(&["-c", "duct"], &FILES[1..2])
+        );
+        assert_eq!(
+            ("2\n".to_string(), true),
+            run_mode(&["-vc", "duct"], &FILES[..1])
+        );
+    }
+
+    #[test]
+    fn files_with_and_without_matches() {
+        assert_eq!(
+            ("a.txt\nc.txt\n".to_string(), true),
+            run_mode(&["-l", "duct"], &FILES)
+        );
+        assert_eq!(
+            ("b.txt\n".to_string(), true),
+            run_mode(&["-L", "duct"], &FILES)
+        );
+        // -L with every file matching lists nothing, so nothing was selected
+        assert_eq!(
+            (String::new(), false),
+            run_mode(&["-L", "duct"], &FILES[2..])
+        );
+        // -l names the file even when it's the only one
+        assert_eq!(
+            ("c.txt\n".to_string(), true),
+            run_mode(&["-l", "duct"], &FILES[2..])
+        );
+    }
+
+    #[test]
+    fn no_match_is_not_selected() {
+        assert_eq!((String::new(), false), run_mode(&["zebra"], &FILES));
+        assert_eq!((String::new(), false), run_mode(&["-j4", "zebra"], &FILES));
+    }
+
+    #[test]
+    fn json_records() {
+        let (out, selected) = run_mode(&["--json", "-j1", "duct"], &FILES[..2]);
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert!(selected);
+        assert_eq!(4, lines.len(), "{}", out);
+        assert!(lines[0].starts_with(r#"{"type":"begin","data":{"path":{"text":""#));
+        assert!(lines[0].ends_with(r#"a.txt"}}}"#));
+        assert!(lines[1].ends_with(
+            r#"a.txt"},"lines":{"text":"safe, fast, productive."},"line_number":2,"column":16,"absolute_offset":6,"submatches":[{"match":{"text":"duct"},"start":15,"end":19}],"patterns":[0]}}"#
+        ));
+        assert!(lines[2].ends_with(r#"a.txt"},"stats":{"matched_lines":1}}}"#));
+        // b.txt has no matches, so it only shows up in the totals
+        assert_eq!(
+            r#"{"type":"summary","data":{"stats":{"files_searched":2,"files_with_matches":1,"matched_lines":1}}}"#,
+            lines[3]
+        );
+    }
+
+    #[test]
+    fn json_same_in_parallel() {
+        assert_eq!(
+            run_mode(&["--json", "-j1", "-C1", "duct"], &FILES),
+            run_mode(&["--json", "-j4", "-C1", "duct"], &FILES)
+        );
+    }
+
+    #[test]
+    fn config_json_conflicts_with_count() {
+        let args = ["cli-proj", "--json", "-c", "q", "f"]
+            .iter()
+            .map(|s| s.to_string());
+
+        assert_eq!(
+            Err(ParseError::ConflictingOptions(
+                "--json".to_string(),
+                "--count".to_string()
+            )),
+            Config::new(args).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn index_as_a_query() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|s| s.to_string()).collect() };
+
+        assert!(is_index_command(&args(&["cli-proj", "index", "logs"])));
+        for query in [
+            args(&["cli-proj", "-e", "index", "logs"]),
+            args(&["cli-proj", "--", "index", "logs"]),
+            args(&["cli-proj", "-i", "index", "logs"]),
+        ] {
+            assert!(!is_index_command(&query));
+            assert_eq!(
+                vec!["index"],
+                Config::new(query.into_iter()).unwrap().patterns
+            );
+        }
+    }
+
+    #[test]
+    fn config_replace_conflicts() {
+        let conflict = |args: &[&str]| {
+            let args = ["cli-proj"].iter().chain(args).map(|s| s.to_string());
+            match Config::new(args) {
+                Err(ParseError::ConflictingOptions(a, b)) => Some((a, b)),
+                _ => None,
+            }
+        };
+
+        for (option, name) in [
+            ("--json", "--json"),
+            ("-c", "--count"),
+            ("-l", "--files-with-matches"),
+            ("-L", "--files-without-match"),
+            ("--column", "--column"),
+            ("-v", "--invert-match"),
+        ] {
+            assert_eq!(
+                Some(("--replace".to_string(), name.to_string())),
+                conflict(&["--replace", "x", option, "q", "f"])
+            );
+        }
+        assert_eq!(None, conflict(&["--replace", "x", "--write", "q", "f"]));
+    }
+
+    #[test]
+    fn config_follow_conflicts() {
+        let conflict = |args: &[&str]| {
+            let args = ["cli-proj"].iter().chain(args).map(|s| s.to_string());
+            match Config::new(args) {
+                Err(ParseError::ConflictingOptions(a, b)) => Some((a, b)),
+                _ => None,
+            }
+        };
+
+        assert_eq!(
+            Some(("--follow".to_string(), "--count".to_string())),
+            conflict(&["--follow", "-c", "q", "f"])
+        );
+        assert_eq!(
+            Some(("--follow".to_string(), "--context".to_string())),
+            conflict(&["--watch", "-C2", "q", "f"])
+        );
+        assert_eq!(
+            Some(("--follow".to_string(), "- (stdin)".to_string())),
+            conflict(&["--follow", "q", "-"])
+        );
+        assert_eq!(None, conflict(&["--follow", "-v", "q", "f"]));
+    }
+
+    #[test]
+    fn colored_output() {
+        let (out, _) = run_mode(&["--color=always", "-j1", "--column", "duct"], &FILES[..1]);
+
+        assert_eq!(
+            "\x1b[35ma.txt\x1b[0m\x1b[36m:\x1b[0m\x1b[32m2\x1b[0m\x1b[36m:\x1b[0m\x1b[32m16\x1b[0m\x1b[36m:\x1b[0m\
+             safe, fast, pro\x1b[1;31mduct\x1b[0mive.\n",
+            out
+        );
+        let (out, _) = run_mode(&["--color", "never", "duct"], &FILES[..1]);
+        assert_eq!("safe, fast, productive.\n", out);
+    }
+
+    #[test]
+    fn config_color_choice() {
+        let args = ["cli-proj", "--color=sometimes", "q", "f"]
+            .iter()
+            .map(|s| s.to_string());
+
+        assert_eq!(
+            Err(ParseError::InvalidChoice {
+                option: "--color".to_string(),
+                value: "sometimes".to_string(),
+                choices: "auto, always, never"
+            }),
+            Config::new(args).map(|_| ())
+        );
+        // tests don't run on a terminal, so auto means no colour
+        let args = ["cli-proj", "q", "f"].iter().map(|s| s.to_string());
+        assert!(!Config::new(args).unwrap().color);
+    }
+
+    #[test]
+    fn config_globs() {
+        let args = [
+            "cli-proj",
+            "-g",
+            "*.rs",
+            "--glob=*.toml",
+            "--exclude",
+            "target/**",
+            "q",
+            ".",
+        ]
+        .iter()
+        .map(|s| s.to_string());
+        assert!(Config::new(args).is_ok());
+
+        let args = ["cli-proj", "--exclude", "[z-a]", "q", "."]
+            .iter()
+            .map(|s| s.to_string());
+        assert_eq!(
+            Err(ParseError::InvalidGlob("[z-a]".to_string())),
+            Config::new(args).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn replace_preview_and_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("poem.txt");
+        fs::write(&path, "Rust:\nsafe, fast, productive.\n").unwrap();
+        let files = vec![path.clone()];
+        let config = |write: bool| {
+            let mut args = vec!["cli-proj", "-E", "--replace", "${1}ION"];
+            if write {
+                args.push("--write");
+            }
+            args.extend_from_slice(&["(duct)ive", path.to_str().unwrap()]);
+            Config::new(args.into_iter().map(|s| s.to_string())).unwrap()
+        };
+
+        let mut out = Vec::new();
+        assert!(search_files(&config(false), &files, false, &mut out).unwrap());
+        let diff = String::from_utf8(out).unwrap();
+        assert!(diff.ends_with(
+            "@@ -1,2 +1,2 @@\n Rust:\n-safe, fast, productive.\n+safe, fast, productION.\n"
+        ));
+        // only a preview, the file is untouched
+        assert_eq!(
+            "Rust:\nsafe, fast, productive.\n",
+            fs::read_to_string(&path).unwrap()
+        );
+
+        search_files(&config(true), &files, false, Vec::new()).unwrap();
+        assert_eq!(
+            "Rust:\nsafe, fast, productION.\n",
+            fs::read_to_string(&path).unwrap()
+        );
+    }
+
+    #[test]
+    fn config_write_requires_replace() {
+        let args = ["cli-proj", "--write", "q", "f"]
+            .iter()
+            .map(|s| s.to_string());
+
+        assert_eq!(
+            Err(ParseError::Requires(
+                "--write".to_string(),
+                "--replace".to_string()
+            )),
+            Config::new(args).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn config_threads() {
+        let args = ["cli-proj", "--threads", "3", "q", "f"]
+            .iter()
+            .map(|s| s.to_string());
+        assert_eq!(3, Config::new(args).unwrap().threads);
+
+        let args = ["cli-proj", "-j", "0", "q", "f"]
+            .iter()
+            .map(|s| s.to_string());
+        assert_eq!(
+            Err(ParseError::InvalidNumber {
+                option: "--threads".to_string(),
+                value: "0".to_string()
+            }),
+            Config::new(args).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn config_invalid_regex() {
+        let args = ["cli-proj", "--regex", "fn (", "file.txt"]
+            .iter()
+            .map(|s| s.to_string());
+
+        match Config::new(args) {
+            Err(ParseError::InvalidRegex(regex::Error::Syntax(_))) => {}
+            _ => panic!("expected a regex syntax error"),
+        }
+    }
+
+    #[test]
+    fn config_case_flags() {
+        let args = ["cli-proj", "-i", "q", "f"].iter().map(|s| s.to_string());
+        assert!(!Config::new(args).unwrap().case_sensitive);
+
+        // the last one given wins
+        let args = ["cli-proj", "-i", "-s", "q", "f"]
+            .iter()
+            .map(|s| s.to_string());
+        assert!(Config::new(args).unwrap().case_sensitive);
+    }
+
+    #[test]
+    fn config_help_and_version() {
+        let args = ["cli-proj", "q", "--help"].iter().map(|s| s.to_string());
+        assert_eq!(
+            Err(ParseError::HelpRequested),
+            Config::new(args).map(|_| ())
+        );
+
+        let args = ["cli-proj", "-V"].iter().map(|s| s.to_string());
+        assert_eq!(
+            Err(ParseError::VersionRequested),
+            Config::new(args).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn config_missing_positionals() {
+        let args = ["cli-proj", "-i"].iter().map(|s| s.to_string());
+        assert_eq!(Err(ParseError::MissingQuery), Config::new(args).map(|_| ()));
+
+        let args = ["cli-proj", "q"].iter().map(|s| s.to_string());
+        assert_eq!(Err(ParseError::MissingPath), Config::new(args).map(|_| ()));
+    }
+
+    #[test]
+    fn multiple_patterns() {
+        let flags = [
+            "-j1",
+            "--show-pattern",
+            "-e",
+            "duct",
+            "-e",
+            "Pick",
+            "-e",
+            "t",
+        ];
+        assert_eq!(
+            (
+                "t:Rust:\nduct,t:safe, fast, productive.\nPick,t:Pick three.\n".to_string(),
+                true
+            ),
+            run_mode(&flags, &FILES[..1])
+        );
+        // the same with regexes, which also makes every positional a path
+        let flags = ["-E", "--show-pattern", "-e", "^R", "-e", "t{2}|ee"];
+        assert_eq!(
+            ("^R:Rust:\nt{2}|ee:Pick three.\n".to_string(), true),
+            run_mode(&flags, &FILES[..1])
+        );
+    }
+
+    #[test]
+    fn config_pattern_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("patterns.txt");
+        fs::write(&file, "foo\nbar\r\n").unwrap();
+        let file = file.to_str().unwrap().to_string();
+
+        let args = ["cli-proj", "-e", "baz", "-f", &file, "a", "b"].map(String::from);
+        let config = Config::new(args.into_iter()).unwrap();
+        assert_eq!(vec!["baz", "foo", "bar"], config.patterns);
+        assert_eq!(vec!["a", "b"], config.paths);
+        assert_eq!(vec![0, 2], config.matcher().patterns_in(b"bar baz"));
+
+        let missing = dir.path().join("missing").to_str().unwrap().to_string();
+        let args = ["cli-proj", "-f", &missing, "a"].map(String::from);
+        assert!(matches!(
+            Config::new(args.into_iter()),
+            Err(ParseError::PatternFile { .. })
+        ));
+    }
+
+    #[test]
+    fn fuzzy_sorted_by_distance() {
+        let files = [("log.txt", "timeuot\ntimeout\ntmeout\ntime out\n")];
+        // a swap is two edits, a missing or extra char is one; ties stay in file order
+        assert_eq!(
+            ("timeout\ntmeout\ntime out\ntimeuot\n".to_string(), true),
+            run_mode(&["--fuzzy", "2", "--sort-distance", "timeout"], &files)
+        );
+        let (out, _) = run_mode(&["--json", "--fuzzy", "1", "timeout"], &files);
+        assert!(
+            out.contains(r#""submatches":[{"match":{"text":"tmeout"},"start":0,"end":6}],"patterns":[0],"distance":1}"#),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn config_fuzzy_conflicts() {
+        let config = |args: &[&str]| {
+            let mut all = vec!["cli-proj"];
+            all.extend(args);
+            Config::new(all.into_iter().map(String::from)).map(|_| ())
+        };
+        assert_eq!(
+            Err(ParseError::Requires(
+                "--sort-distance".to_string(),
+                "--fuzzy".to_string()
+            )),
+            config(&["--sort-distance", "q", "f"])
+        );
+        assert_eq!(
+            Err(ParseError::ConflictingOptions(
+                "--fuzzy".to_string(),
+                "--regex".to_string()
+            )),
+            config(&["--fuzzy=1", "-E", "q", "f"])
+        );
+        assert!(config(&["--fuzzy", "1", "--sort-distance", "-C1", "q", "f"]).is_err());
+        assert!(config(&["--fuzzy", "1", "-e", "a", "-e", "b", "f"]).is_err());
+    }
+
+    #[test]
+    fn only_in_rust_syntax() {
+        let files = [(
+            "a.rs",
+            "// parse the config
+fn parse(config: &str) {
+    /* config
+       parse */ let s = \"parse\";
+}
+",
+        )];
+        assert_eq!(
+            ("fn parse(config: &str) {\n".to_string(), true),
+            run_mode(&["--only", "identifier", "parse"], &files)
+        );
+        // the comment that starts on line 3 is still a comment on line 4
+        assert_eq!(
+            (
+                "a.rs:1:4:// parse the config\na.rs:4:8:       parse */ let s = \"parse\";\n"
+                    .to_string(),
+                true
+            ),
+            run_mode(&["--only=comment", "--column", "parse"], &files)
+        );
+        assert_eq!(
+            (
+                "a.rs:4:26:       parse */ let s = \"parse\";\n".to_string(),
+                true
+            ),
+            run_mode(&["--only", "string", "--column", "parse"], &files)
+        );
+        assert_eq!(
+            ("".to_string(), false),
+            run_mode(&["--only", "string", "config"], &files)
+        );
+
+        // only the .rs files of a directory, but a file named on its own whatever it's called
+        let dir = tempfile::tempdir().unwrap();
+        let elsewhere = tempfile::tempdir().unwrap();
+        let named = elsewhere.path().join("build.rs.in");
+        for file in [
+            dir.path().join("a.rs"),
+            dir.path().join("b.txt"),
+            named.clone(),
+        ] {
+            fs::write(file, "fn parse() {}\n").unwrap();
+        }
+        let args = ["cli-proj", "--only=identifier", "parse"]
+            .into_iter()
+            .map(String::from)
+            .chain([dir.path(), &named].map(|p| p.to_str().unwrap().to_string()));
+        let config = Config::new(args).unwrap();
+        let files = walk::collect_files(&config.paths, &config.filter).unwrap();
+        assert_eq!(
+            vec![dir.path().join("a.rs"), named.clone()],
+            rust_files(&config, files)
+        );
+
+        let config = |args: &[&str]| {
+            let args = ["cli-proj"].iter().chain(args).map(|s| s.to_string());
+            Config::new(args).map(|_| ())
+        };
+        assert_eq!(
+            Err(ParseError::ConflictingOptions(
+                "--only".to_string(),
+                "--invert-match".to_string()
+            )),
+            config(&["--only", "comment", "-v", "q", "f"])
+        );
+        assert!(matches!(
+            config(&["--only", "string,docs", "q", "f"]),
+            Err(ParseError::InvalidChoice { .. })
+        ));
+    }
+
+    #[test]
+    fn word_and_line_flags() {
+        let files = [("a.txt", "foobar\nfoo bar\nfoo\n")];
+        assert_eq!(
+            ("foo bar\nfoo\n".to_string(), true),
+            run_mode(&["-w", "foo"], &files)
+        );
+        assert_eq!(
+            ("foo\n".to_string(), true),
+            run_mode(&["-x", "foo"], &files)
+        );
+        // -x wins over -w
+        assert_eq!(
+            ("foo\n".to_string(), true),
+            run_mode(&["-xw", "-i", "FOO"], &files)
+        );
+        // the regex gets the boundary built in, so foobar is picked over foo
+        assert_eq!(
+            ("foobar\nfoo bar\nfoo\n".to_string(), true),
+            run_mode(&["-wE", "foo|foobar"], &files)
+        );
+        assert_eq!(
+            ("foobar\nfoo\n".to_string(), true),
+            run_mode(&["-x", "-E", "-e", "foo", "-e", "fo+bar"], &files)
+        );
+        // every line has one of the words in it, so -v is left with nothing
+        assert_eq!(
+            (String::new(), false),
+            run_mode(&["-wv", "-e", "foo", "-e", "foobar"], &files)
+        );
+    }
+
+    #[test]
+    fn gzip_same_as_plain() {
+        use flate2::write::GzEncoder;
+
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("a.txt");
+        fs::write(&plain, FILES[0].1).unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(FILES[0].1.as_bytes()).unwrap();
+        // no .gz on the name, so it's only spotted by its magic bytes
+        let compressed = dir.path().join("a.txt.1");
+        fs::write(&compressed, encoder.finish().unwrap()).unwrap();
+
+        let search = |file: &PathBuf, flags: &[&str]| {
+            let mut args = vec!["cli-proj".to_string()];
+            args.extend(flags.iter().map(|s| s.to_string()));
+            args.push(file.to_str().unwrap().to_string());
+            let config = Config::new(args.into_iter()).unwrap();
+            let mut out = Vec::new();
+            search_files(&config, std::slice::from_ref(file), false, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        for flags in [&["-C1", "duct"][..], &["--column", "t"], &["-c", "e"]] {
+            assert_eq!(
+                search(&plain, flags).replace("a.txt", ""),
+                search(&compressed, flags).replace("a.txt.1", "")
+            );
+        }
+        assert_eq!("safe, fast, productive.\n", search(&compressed, &["duct"]));
+        // and a .gz name on a file that isn't compressed doesn't make it one
+        let misnamed = dir.path().join("notes.gz");
+        fs::write(&misnamed, FILES[0].1).unwrap();
+        assert_eq!("safe, fast, productive.\n", search(&misnamed, &["duct"]));
+    }
+
+    #[test]
+    fn binary_files() {
+        let files = [
+            ("a.txt", "needle\n"),
+            ("b.bin", "\x7fELF\0\nneedle\nneedle\n"),
+        ];
+        assert_eq!(
+            (
+                "a.txt:needle\nBinary file b.bin matches\n".to_string(),
+                true
+            ),
+            run_mode(&["-j1", "needle"], &files)
+        );
+        assert_eq!(
+            (
+                "a.txt:needle\nb.bin:needle\nb.bin:needle\n".to_string(),
+                true
+            ),
+            run_mode(&["-j1", "--text", "needle"], &files)
+        );
+        assert_eq!(
+            ("a.txt:1\nb.bin:2\n".to_string(), true),
+            run_mode(&["-c", "needle"], &files)
+        );
+        assert_eq!((String::new(), false), run_mode(&["hay"], &files));
+    }
+
+    #[test]
+    fn invalid_utf8_is_searched_as_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let latin1 = dir.path().join("latin1.txt");
+        fs::write(&latin1, b"caf\xe9 au lait\nth\xe9\n").unwrap();
+        let search = |flags: &[&str]| {
+            let args = ["cli-proj"]
+                .iter()
+                .chain(flags)
+                .map(|s| s.to_string())
+                .chain([latin1.to_str().unwrap().to_string()]);
+            let config = Config::new(args).unwrap();
+            let mut out = Vec::new();
+            search_files(&config, std::slice::from_ref(&latin1), false, &mut out).unwrap();
+            String::from_utf8(out)
+                .unwrap()
+                .replace(latin1.to_str().unwrap(), "f")
+        };
+
+        // the line isn't UTF-8, so JSON gets its bytes as they are; offsets count the é as the one byte it is
+        let json = search(&["-e", "lait", "-e", "th", "--json"]);
+        assert!(
+            json.contains(r#""lines":{"bytes":"Y2Fm6SBhdSBsYWl0"},"line_number":1,"column":9,"absolute_offset":0,"submatches":[{"match":{"text":"lait"},"start":8,"end":12}]"#),
+            "{}",
+            json
+        );
+        assert!(
+            json.contains(r#""line_number":2,"column":1,"absolute_offset":13"#),
+            "{}",
+            json
+        );
+        // printed as text it's decoded, with the byte that isn't UTF-8 as U+FFFD
+        assert_eq!("f:1:9:caf\u{FFFD} au lait\n", search(&["--column", "lait"]));
+        // \xe9 in a regex is the char é, which is two bytes in UTF-8 and not the Latin-1 one
+        assert_eq!("", search(&["-E", r"caf\x{e9}"]));
+        assert_eq!("", search(&["-E", r"caf\xe9"]));
+        // the byte itself has to be asked for with Unicode turned off
+        assert_eq!(
+            "caf\u{FFFD} au lait\n",
+            search(&["-E", r"caf(?-u:\xe9) au"])
+        );
+        assert_eq!("th\u{FFFD}\n", search(&["-E", r"th(?-u:\xe9)$"]));
+    }
+
+    #[test]
+    fn hex_escapes_are_chars() {
+        let dir = tempfile::tempdir().unwrap();
+        let utf8 = dir.path().join("utf8.txt");
+        fs::write(&utf8, "café au lait\n").unwrap();
+        let config = Config::new(
+            ["cli-proj", "-E", r"caf\x{e9}", utf8.to_str().unwrap()]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        search_files(&config, std::slice::from_ref(&utf8), false, &mut out).unwrap();
+
+        assert_eq!("café au lait\n", String::from_utf8(out).unwrap());
+    }
 }
@@ -1,12 +1,28 @@
 use std::env;
 use std::process;
 
+use cli_proj::is_index_command;
 use cli_proj::run;
+use cli_proj::run_index;
 use cli_proj::Config;
+use cli_proj::ParseError;
 
 fn main() {
     // i.e. cargo run stuff ./file.txt
-    // or w/ env variable: CASE_INSENSITIVE=1 cargo run STUFF ./file.txt
+    // or w/ env variable: CASE_INSENSITIVE=1 cargo run STUFF ./file.txt (or the -i flag, see --help)
+    // or as a regular expression: cargo run -- -E "^more \w+" ./file.txt
+    // or recursively over directories: cargo run stuff ./src ./file.txt
+    // or with positions for an editor: cargo run -- --column stuff ./file.txt
+    // or with 2 lines of context around each match: cargo run -- -C 2 stuff ./file.txt
+    // or from stdin: tail -f log.txt | cargo run stuff -
+    // or over a big tree on 4 threads: cargo run -- -j 4 stuff ./src
+    // or for several words at once: cargo run -- -e stuff -e more -f words.txt ./file.txt
+    // or allowing for typos, closest first: cargo run -- --fuzzy 2 --sort-distance recieved ./log.txt
+    // or only as a whole word: cargo run -- -w duct ./file.txt
+    // or inside rotated, gzipped logs: cargo run -- timeout ./logs/app.log.1.gz
+    // or only where it's code, not in comments or strings: cargo run -- --only identifier Matcher ./src
+    // or following a log as it's written (and rotated): cargo run -- --follow timeout ./logs/app.log
+    // or with an index, for a big tree searched over and over: cargo run index ./logs, then cargo run timeout ./logs
 
     /*
     Old, inefficent way which results in cloning our args into new variables (see lib):
@@ -18,13 +34,37 @@ fn main() {
     });
     */
 
-    let config = Config::new(env::args()).unwrap_or_else(|err| {
-        eprintln!("Problem parsing args: {}", err);
-        process::exit(1);
+    // `cli-proj index DIR...` is a command of its own rather than a search
+    let args: Vec<String> = env::args().collect();
+    if is_index_command(&args) {
+        if let Err(e) = run_index(args.into_iter().skip(2)) {
+            eprintln!("Application error: {}", e);
+            process::exit(2);
+        }
+        return;
+    }
+
+    let config = Config::new(env::args()).unwrap_or_else(|err| match err {
+        // asking for help or the version isn't a mistake, so it goes to stdout and exits successfully
+        ParseError::HelpRequested | ParseError::VersionRequested => {
+            println!("{}", err);
+            process::exit(0);
+        }
+        _ => {
+            eprintln!("Problem parsing args: {}", err);
+            eprintln!("Try 'cli-proj --help' for more information.");
+            process::exit(2);
+        }
     });
 
-    if let Err(e) = run(config) {
-        eprintln!("Application error: {}", e);
-        process::exit(1);
+    // grep's exit status conventions, so cli-proj can be used in shell pipelines and `if` tests:
+    // 0 when something was selected, 1 when nothing was, 2 when there was an error
+    match run(config) {
+        Ok(true) => process::exit(0),
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("Application error: {}", e);
+            process::exit(2);
+        }
     }
 }
@@ -0,0 +1,426 @@
+// A Matcher knows how to find every hit of the query inside a single line.
+// The search functions in lib.rs only deal with lines, the Matcher deals with what "matches" means.
+// Lines are raw bytes (see utf8.rs), and every hit is a byte range of the line as it is in the file.
+use std::borrow::Cow;
+use std::ops::Range;
+
+use memchr::memmem;
+use regex::bytes::{Regex, RegexSet};
+use regex_syntax::is_word_character;
+
+use crate::fold::FoldedQuery;
+use crate::fuzzy::FuzzyQuery;
+use crate::lexer::{Lexer, Syntax};
+use crate::multi::MultiPattern;
+use crate::utf8;
+
+pub enum Matcher {
+    // plain substring, the behaviour of the original search function
+    Literal(String),
+    // substring ignoring case, using Unicode case folding (see fold.rs)
+    CaseInsensitive(FoldedQuery),
+    Regex(Regex),
+    // several patterns (-e/-f), found together in one pass over each line
+    Patterns(MultiPattern),
+    // --fuzzy: within a few typos of the query, see fuzzy.rs
+    Fuzzy(FuzzyQuery),
+    // several regexes: one alternation of them all to find the hits, and a set to tell which ones hit
+    RegexSet(Regex, RegexSet),
+    // -w/-x: only the hits of the inner matcher that are whole words, or the whole line
+    // (a regex gets the boundary built into it instead, see Boundary::wrap_regex)
+    Bounded(Box<Matcher>, Boundary),
+    // -v: selects the lines the inner matcher doesn't, there's nothing to highlight on them
+    Inverted(Box<Matcher>),
+    // --only: the hits of the inner matcher that lie inside identifiers, strings or comments of Rust
+    // source. Searching a file lexes it line after line (see Matcher::within and stream.rs); asked
+    // about a line on its own, the line is lexed as if it started outside any comment or string.
+    Within(Box<Matcher>, Vec<Syntax>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Boundary {
+    // -w: a hit can't have a word char (Unicode's \w: letters, marks, digits, connector
+    // punctuation) just before or just after it, so "duct" isn't a word in "productive"
+    Word,
+    // -x: a hit has to be the entire line
+    Line,
+}
+
+impl Boundary {
+    // A regex can be told about the boundary itself, so alternatives that can't pass (the "foo" of
+    // foo|foobar in "foobar") aren't picked over ones that can. \b{start-half} and \b{end-half} are
+    // the regex crate's "no word char before/after", the same test `allows` does.
+    pub fn wrap_regex(self, pattern: &str) -> String {
+        match self {
+            Boundary::Word => format!(r"\b{{start-half}}(?:{})\b{{end-half}}", pattern),
+            Boundary::Line => format!("^(?:{})$", pattern),
+        }
+    }
+
+    fn allows(self, line: &[u8], hit: &Range<usize>) -> bool {
+        if hit.is_empty() {
+            return false;
+        }
+        match self {
+            Boundary::Word => {
+                !utf8::char_before(line, hit.start).is_some_and(is_word_character)
+                    && !utf8::decode(&line[hit.end..]).is_some_and(|(c, _)| is_word_character(c))
+            }
+            Boundary::Line => *hit == (0..line.len()),
+        }
+    }
+}
+
+impl Matcher {
+    pub fn literal(query: &str, case_sensitive: bool) -> Matcher {
+        if case_sensitive {
+            Matcher::Literal(query.to_string())
+        } else {
+            Matcher::CaseInsensitive(FoldedQuery::new(query))
+        }
+    }
+
+    pub fn is_match(&self, line: &[u8]) -> bool {
+        match self {
+            Matcher::Literal(query) => memmem::find(line, query.as_bytes()).is_some(),
+            Matcher::CaseInsensitive(query) => {
+                query.is_empty() || query.find_from(line, 0).is_some()
+            }
+            Matcher::Regex(re) | Matcher::RegexSet(re, _) => re.is_match(line),
+            Matcher::Patterns(patterns) => patterns.is_match(line),
+            Matcher::Fuzzy(query) => query.is_empty() || query.distance(line).is_some(),
+            // an empty line has no hits to check, but an empty query is still the whole of it
+            Matcher::Bounded(inner, _) if line.is_empty() => inner.is_match(line),
+            Matcher::Bounded(_, _) | Matcher::Within(_, _) => !self.find_all(line).is_empty(),
+            Matcher::Inverted(inner) => !inner.is_match(line),
+        }
+    }
+
+    // byte ranges (relative to the start of the line) of every non-overlapping hit, left to right
+    pub fn find_all(&self, line: &[u8]) -> Vec<Range<usize>> {
+        match self {
+            Matcher::Literal(query) => literal_ranges(line, query),
+            Matcher::CaseInsensitive(query) => {
+                let mut ranges = Vec::new();
+                if query.is_empty() {
+                    return ranges;
+                }
+                let mut from = 0;
+                while let Some(hit) = query.find_from(line, from) {
+                    from = hit.end;
+                    ranges.push(hit);
+                }
+                ranges
+            }
+            Matcher::Regex(re) | Matcher::RegexSet(re, _) => {
+                re.find_iter(line).map(|m| m.range()).collect()
+            }
+            Matcher::Patterns(patterns) => patterns
+                .find_all(line)
+                .into_iter()
+                .map(|hit| hit.range)
+                .collect(),
+            Matcher::Fuzzy(query) => {
+                let mut ranges = Vec::new();
+                if query.is_empty() {
+                    return ranges;
+                }
+                let mut from = 0;
+                while let Some(hit) = query.find_from(line, from) {
+                    from = hit.end;
+                    ranges.push(hit);
+                }
+                ranges
+            }
+            Matcher::Bounded(inner, boundary) => {
+                let allows = |hit: &Range<usize>| boundary.allows(line, hit);
+                if let Matcher::Patterns(patterns) = inner.as_ref() {
+                    return patterns
+                        .find_all_where(line, allows)
+                        .into_iter()
+                        .map(|hit| hit.range)
+                        .collect();
+                }
+                inner.find_all_where(line, allows)
+            }
+            Matcher::Inverted(_) => Vec::new(),
+            Matcher::Within(_, kinds) => {
+                self.find_all_in(line, &Lexer::default().next_line(line, kinds))
+            }
+        }
+    }
+
+    // Every hit `allows` accepts. Every place a hit could start is tried, since a rejected hit may
+    // overlap an accepted one (searching for the word "ab" in "abab ab").
+    fn find_all_where(
+        &self,
+        line: &[u8],
+        allows: impl Fn(&Range<usize>) -> bool,
+    ) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut from = 0;
+        while let Some(hit) = self.find_at(line, from) {
+            if allows(&hit) {
+                from = hit.end;
+                ranges.push(hit);
+            } else {
+                match utf8::decode(&line[hit.start..]) {
+                    Some((_, len)) => from = hit.start + len,
+                    None => break,
+                }
+            }
+        }
+        ranges
+    }
+
+    // The first hit starting at or after byte `from`, for Bounded to retry from. Empty queries
+    // have nothing to point at, so there's no hit at all.
+    fn find_at(&self, line: &[u8], from: usize) -> Option<Range<usize>> {
+        match self {
+            Matcher::Literal(query) if !query.is_empty() => {
+                memmem::find(&line[from..], query.as_bytes())
+                    .map(|start| from + start..from + start + query.len())
+            }
+            Matcher::CaseInsensitive(query) if !query.is_empty() => query.find_from(line, from),
+            Matcher::Regex(re) | Matcher::RegexSet(re, _) => {
+                re.find_at(line, from).map(|m| m.range())
+            }
+            Matcher::Fuzzy(query) if !query.is_empty() => query.find_from(line, from),
+            Matcher::Patterns(patterns) => patterns
+                .find_all_where(line, |hit| hit.start >= from)
+                .into_iter()
+                .next()
+                .map(|hit| hit.range),
+            Matcher::Bounded(inner, boundary) => {
+                let mut from = from;
+                while let Some(hit) = inner.find_at(line, from) {
+                    if boundary.allows(line, &hit) {
+                        return Some(hit);
+                    }
+                    from = hit.start + utf8::decode(&line[hit.start..])?.1;
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    // Which of the patterns (by their position on the command line) occur in a matching line.
+    // With a single query that's always just the first one; -v lines didn't match any.
+    pub fn patterns_in(&self, line: &[u8]) -> Vec<usize> {
+        match self {
+            Matcher::Patterns(patterns) => patterns.patterns_in(line),
+            Matcher::RegexSet(_, set) => set.matches(line).into_iter().collect(),
+            Matcher::Bounded(inner, boundary) => match inner.as_ref() {
+                Matcher::Patterns(patterns) => {
+                    patterns.patterns_in_where(line, |hit| boundary.allows(line, hit))
+                }
+                // with a single pattern it's enough that the line matched at all
+                _ if self.is_match(line) => inner.patterns_in(line),
+                _ => Vec::new(),
+            },
+            Matcher::Inverted(_) => Vec::new(),
+            Matcher::Within(_, kinds) => {
+                self.patterns_in_regions(line, &Lexer::default().next_line(line, kinds))
+            }
+            _ => vec![0],
+        }
+    }
+
+    // how many edits away from the query a matching line is with --fuzzy, None for the exact matchers
+    pub fn distance(&self, line: &[u8]) -> Option<usize> {
+        match self {
+            Matcher::Fuzzy(query) => query.distance(line),
+            Matcher::Bounded(inner, _) | Matcher::Within(inner, _) => inner.distance(line),
+            _ => None,
+        }
+    }
+
+    // --only, searching a file from the top: a Lexer to follow it with, line after line, so a line
+    // inside a block comment or a multi-line string is known to be
+    pub(crate) fn lexer(&self) -> Option<Lexer> {
+        matches!(self, Matcher::Within(_, _)).then(Lexer::default)
+    }
+
+    // the parts of the next line that hits are allowed in, as the file's lexer sees them
+    pub(crate) fn regions(&self, line: &[u8], lexer: &mut Lexer) -> Vec<Range<usize>> {
+        match self {
+            Matcher::Within(_, kinds) => lexer.next_line(line, kinds),
+            // anywhere in the line
+            _ => {
+                let whole = 0..line.len();
+                vec![whole]
+            }
+        }
+    }
+
+    // find_all and patterns_in, with the hits limited to `regions` (from Matcher::regions)
+    pub(crate) fn find_all_in(&self, line: &[u8], regions: &[Range<usize>]) -> Vec<Range<usize>> {
+        match self {
+            Matcher::Within(inner, _) => inner.find_all_where(line, |hit| inside(regions, hit)),
+            _ => self.find_all(line),
+        }
+    }
+
+    pub(crate) fn patterns_in_regions(&self, line: &[u8], regions: &[Range<usize>]) -> Vec<usize> {
+        match self {
+            Matcher::Within(inner, _) => match inner.as_ref() {
+                Matcher::Patterns(patterns) => {
+                    patterns.patterns_in_where(line, |hit| inside(regions, hit))
+                }
+                _ if !self.find_all_in(line, regions).is_empty() => inner.patterns_in(line),
+                _ => Vec::new(),
+            },
+            _ => self.patterns_in(line),
+        }
+    }
+}
+
+// whether a hit lies entirely inside one of the regions (an empty hit has nothing to point at)
+fn inside(regions: &[Range<usize>], hit: &Range<usize>) -> bool {
+    !hit.is_empty()
+        && regions
+            .iter()
+            .any(|region| region.start <= hit.start && hit.end <= region.end)
+}
+
+impl Matcher {
+    // The line with every hit replaced (--replace). In regex mode the replacement can refer to
+    // capture groups: $1, ${1}, ${name} ($$ for a literal $); otherwise it's inserted as-is.
+    pub fn replace_all<'a>(&self, line: &'a [u8], replacement: &str) -> Cow<'a, [u8]> {
+        match self {
+            Matcher::Regex(re) | Matcher::RegexSet(re, _) => {
+                re.replace_all(line, replacement.as_bytes())
+            }
+            Matcher::Inverted(_) => Cow::Borrowed(line),
+            _ => {
+                let hits = self.find_all(line);
+                if hits.is_empty() {
+                    return Cow::Borrowed(line);
+                }
+                let mut out = Vec::with_capacity(line.len());
+                let mut last = 0;
+                for hit in hits {
+                    out.extend_from_slice(&line[last..hit.start]);
+                    out.extend_from_slice(replacement.as_bytes());
+                    last = hit.end;
+                }
+                out.extend_from_slice(&line[last..]);
+                Cow::Owned(out)
+            }
+        }
+    }
+}
+
+fn literal_ranges(line: &[u8], query: &str) -> Vec<Range<usize>> {
+    // an empty query matches every line, but there is nothing to highlight
+    if query.is_empty() {
+        return Vec::new();
+    }
+    memmem::find_iter(line, query.as_bytes())
+        .map(|start| start..start + query.len())
+        .collect()
+}
+
+// A single matching line, with enough position information to jump to it in an editor.
+#[derive(Debug, PartialEq)]
+pub struct Match<'a> {
+    // 1-based, like grep -n
+    pub line_number: usize,
+    // offset of the start of the line from the start of the contents
+    pub byte_offset: usize,
+    // byte ranges of each hit within `line`
+    pub columns: Vec<Range<usize>>,
+    // indexes of the patterns found on the line (see Matcher::patterns_in)
+    pub patterns: Vec<usize>,
+    // --fuzzy: the number of edits between the query and the closest part of the line
+    pub distance: Option<usize>,
+    // the line as it is in the file (without its "\n" or "\r\n"), which needn't be UTF-8
+    pub line: &'a [u8],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal_finds_every_hit() {
+        let matcher = Matcher::literal("ab", true);
+
+        assert_eq!(vec![0..2, 4..6], matcher.find_all(b"ab--ab"));
+    }
+
+    #[test]
+    fn case_insensitive_finds_every_hit() {
+        let matcher = Matcher::literal("SS", false);
+
+        // each ß is a whole "ss" on its own, and hits don't overlap
+        assert_eq!(vec![0..2, 2..4, 4..6], matcher.find_all("ßßsss".as_bytes()));
+    }
+
+    #[test]
+    fn inverted_selects_the_other_lines() {
+        let matcher = Matcher::Inverted(Box::new(Matcher::literal("ab", true)));
+
+        assert!(!matcher.is_match(b"ab--ab"));
+        assert!(matcher.is_match(b"ba"));
+        assert!(matcher.find_all(b"ba").is_empty());
+        assert!(matcher.patterns_in(b"ba").is_empty());
+    }
+
+    #[test]
+    fn whole_words() {
+        let word = |query, case_sensitive| {
+            Matcher::Bounded(
+                Box::new(Matcher::literal(query, case_sensitive)),
+                Boundary::Word,
+            )
+        };
+
+        // a rejected hit can overlap the one that counts
+        assert_eq!(vec![5..7], word("ab", true).find_all(b"abab ab"));
+        assert!(!word("duct", true).is_match(b"productive"));
+        assert!(word("duct", true).is_match(b"duct-tape"));
+        // letters beyond ASCII are word chars too
+        assert!(!word("na", true).is_match("naïve".as_bytes()));
+        assert_eq!(
+            vec![4..9],
+            word("ÉTÉ", false).find_all("cet été-là".as_bytes())
+        );
+    }
+
+    #[test]
+    fn whole_lines() {
+        let line = Matcher::Bounded(Box::new(Matcher::literal("rust", false)), Boundary::Line);
+
+        assert!(line.is_match(b"Rust"));
+        assert!(!line.is_match(b"Rust:"));
+        // an empty query as a whole line is an empty line
+        let empty = Matcher::Bounded(Box::new(Matcher::literal("", true)), Boundary::Line);
+        assert!(empty.is_match(b""));
+        assert!(!empty.is_match(b" "));
+    }
+
+    #[test]
+    fn whole_words_from_several_patterns() {
+        let patterns = ["foo".to_string(), "foobar".to_string()];
+        let matcher = Matcher::Bounded(
+            Box::new(Matcher::Patterns(MultiPattern::new(&patterns, true))),
+            Boundary::Word,
+        );
+
+        assert_eq!(vec![0..6], matcher.find_all(b"foobar"));
+        assert_eq!(vec![1], matcher.patterns_in(b"foobar"));
+        assert_eq!(vec![0], matcher.patterns_in(b"foo bar"));
+    }
+
+    #[test]
+    fn regex_set_tells_which_patterns_hit() {
+        let patterns = [r"\d+", "[a-z]+", "x"];
+        let alternation = Regex::new(r"(?:\d+)|(?:[a-z]+)|(?:x)").unwrap();
+        let matcher = Matcher::RegexSet(alternation, RegexSet::new(patterns).unwrap());
+
+        assert_eq!(vec![0, 1], matcher.patterns_in(b"abc 123"));
+        assert_eq!(vec![0..3, 4..7], matcher.find_all(b"abc 123"));
+    }
+}
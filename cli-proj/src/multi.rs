@@ -0,0 +1,275 @@
+// Searching for many patterns at once (-e foo -e bar, -f FILE).
+// Looping over the patterns would scan every line once per pattern; an Aho-Corasick automaton
+// finds all of them in a single pass over the line, however many patterns there are.
+// The automaton works on chars (read from the line's bytes by utf8.rs, so hits are byte ranges of the
+// raw line), and case-insensitive search folds both the patterns and the line with the same fold()
+// as fold.rs, so "ß" still matches "SS".
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::fold::fold_if;
+use crate::utf8;
+
+pub struct MultiPattern {
+    case_sensitive: bool,
+    states: Vec<State>,
+    // length of each pattern in (folded) chars, to walk back from the end of a hit to its start
+    lengths: Vec<usize>,
+    // an empty pattern matches every line, like an empty query
+    has_empty: bool,
+}
+
+#[derive(Default)]
+struct State {
+    next: HashMap<char, usize>,
+    // where to carry on from when the next char doesn't extend the current prefix
+    fail: usize,
+    // every pattern that ends here, including the ones reached through `fail`
+    out: Vec<usize>,
+}
+
+// One pattern found in a line: its index in the pattern list and where it sits in the line.
+#[derive(Debug, PartialEq)]
+pub struct Hit {
+    pub pattern: usize,
+    pub range: Range<usize>,
+}
+
+impl MultiPattern {
+    pub fn new(patterns: &[String], case_sensitive: bool) -> MultiPattern {
+        let mut states = vec![State::default()];
+        let mut lengths = Vec::with_capacity(patterns.len());
+        let mut has_empty = false;
+
+        // 1. a trie of all the patterns
+        for (index, pattern) in patterns.iter().enumerate() {
+            let mut state = 0;
+            let mut length = 0;
+            for c in pattern.chars() {
+                for c in fold_if(c, !case_sensitive) {
+                    length += 1;
+                    state = match states[state].next.get(&c) {
+                        Some(&next) => next,
+                        None => {
+                            states.push(State::default());
+                            let next = states.len() - 1;
+                            states[state].next.insert(c, next);
+                            next
+                        }
+                    };
+                }
+            }
+            if length == 0 {
+                has_empty = true;
+            } else {
+                states[state].out.push(index);
+            }
+            lengths.push(length);
+        }
+
+        // 2. failure links, breadth first so a state's fail target is always finished before it
+        let mut queue: Vec<usize> = states[0].next.values().copied().collect();
+        let mut head = 0;
+        while head < queue.len() {
+            let state = queue[head];
+            head += 1;
+            let edges: Vec<(char, usize)> =
+                states[state].next.iter().map(|(&c, &s)| (c, s)).collect();
+            for (c, next) in edges {
+                let mut fail = states[state].fail;
+                let target = loop {
+                    if let Some(&target) = states[fail].next.get(&c) {
+                        break target;
+                    }
+                    if fail == 0 {
+                        break 0;
+                    }
+                    fail = states[fail].fail;
+                };
+                states[next].fail = target;
+                let inherited = states[target].out.clone();
+                states[next].out.extend(inherited);
+                queue.push(next);
+            }
+        }
+
+        MultiPattern {
+            case_sensitive,
+            states,
+            lengths,
+            has_empty,
+        }
+    }
+
+    pub fn is_match(&self, line: &[u8]) -> bool {
+        self.has_empty || !self.hits(line, true).is_empty()
+    }
+
+    // Non-overlapping hits, left to right; where two patterns start at the same place the
+    // longer one wins, so "-e foo -e foobar" highlights all of "foobar".
+    pub fn find_all(&self, line: &[u8]) -> Vec<Hit> {
+        self.find_all_where(line, |_| true)
+    }
+
+    // the same, but only out of the hits `keep` accepts (-w/-x), so a longer hit that's rejected
+    // doesn't hide a shorter one starting at the same place
+    pub fn find_all_where(&self, line: &[u8], keep: impl Fn(&Range<usize>) -> bool) -> Vec<Hit> {
+        let mut hits = self.hits(line, false);
+        hits.retain(|hit| keep(&hit.range));
+        hits.sort_by(|a, b| {
+            a.range
+                .start
+                .cmp(&b.range.start)
+                .then(b.range.end.cmp(&a.range.end))
+        });
+        let mut chosen: Vec<Hit> = Vec::with_capacity(hits.len());
+        for hit in hits {
+            if chosen
+                .last()
+                .is_none_or(|last| hit.range.start >= last.range.end)
+            {
+                chosen.push(hit);
+            }
+        }
+        chosen
+    }
+
+    // indexes of every pattern that occurs somewhere in the line, overlapping or not
+    pub fn patterns_in(&self, line: &[u8]) -> Vec<usize> {
+        let mut found = self.patterns_in_where(line, |_| true);
+        // an empty pattern is found on every line
+        found.extend((0..self.lengths.len()).filter(|&index| self.lengths[index] == 0));
+        found.sort_unstable();
+        found
+    }
+
+    // the same, counting only the hits `keep` accepts
+    pub fn patterns_in_where(
+        &self,
+        line: &[u8],
+        keep: impl Fn(&Range<usize>) -> bool,
+    ) -> Vec<usize> {
+        let mut found = vec![false; self.lengths.len()];
+        for hit in self.hits(line, false) {
+            found[hit.pattern] |= keep(&hit.range);
+        }
+        found
+            .iter()
+            .enumerate()
+            .filter_map(|(index, &found)| found.then_some(index))
+            .collect()
+    }
+
+    // Every hit, overlapping ones included. A hit has to cover whole chars of the line: with
+    // folding one char can turn into several ("ß" into "ss") and half of it doesn't count.
+    fn hits(&self, line: &[u8], first_only: bool) -> Vec<Hit> {
+        let mut hits = Vec::new();
+        // for each folded char seen so far: the byte offset of the char it came from, or None
+        // when it isn't the first char that char folded into
+        let mut starts: Vec<Option<usize>> = Vec::new();
+        let mut state = 0;
+        for (bytes, c) in utf8::char_indices(line) {
+            let (offset, end) = (bytes.start, bytes.end);
+            let mut folded = fold_if(c, !self.case_sensitive).peekable();
+            let mut first = true;
+            while let Some(f) = folded.next() {
+                starts.push(if first { Some(offset) } else { None });
+                first = false;
+                state = self.step(state, f);
+                if folded.peek().is_some() {
+                    continue;
+                }
+                for &pattern in &self.states[state].out {
+                    let at = starts.len() - self.lengths[pattern];
+                    if let Some(start) = starts[at] {
+                        hits.push(Hit {
+                            pattern,
+                            range: start..end,
+                        });
+                        if first_only {
+                            return hits;
+                        }
+                    }
+                }
+            }
+        }
+        hits
+    }
+
+    fn step(&self, mut state: usize, c: char) -> usize {
+        loop {
+            if let Some(&next) = self.states[state].next.get(&c) {
+                return next;
+            }
+            if state == 0 {
+                return 0;
+            }
+            state = self.states[state].fail;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(list: &[&str]) -> Vec<String> {
+        list.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn finds_every_pattern_in_one_pass() {
+        let multi = MultiPattern::new(&patterns(&["he", "she", "his", "hers"]), true);
+
+        // "she", "he" and "hers" all overlap in "ushers"
+        assert_eq!(vec![0, 1, 3], multi.patterns_in(b"ushers"));
+        assert_eq!(
+            vec![Hit {
+                pattern: 1,
+                range: 1..4
+            }],
+            multi.find_all(b"ushers")
+        );
+        assert!(!multi.is_match(b"hi s"));
+    }
+
+    #[test]
+    fn prefers_leftmost_then_longest() {
+        let multi = MultiPattern::new(&patterns(&["foo", "foobar", "bar"]), true);
+
+        assert_eq!(
+            vec![
+                Hit {
+                    pattern: 1,
+                    range: 0..6
+                },
+                Hit {
+                    pattern: 2,
+                    range: 7..10
+                },
+            ],
+            multi.find_all(b"foobar bar")
+        );
+    }
+
+    #[test]
+    fn folds_case_on_whole_chars() {
+        let multi = MultiPattern::new(&patterns(&["STRASSE", "s"]), false);
+
+        let hits = multi.find_all("Straße".as_bytes());
+        assert_eq!(1, hits.len());
+        assert_eq!(0..7, hits[0].range);
+        assert_eq!(vec![0, 1], multi.patterns_in("Straße".as_bytes()));
+        // "s" is only half of "ß", which doesn't count as a hit of its own
+        assert!(!MultiPattern::new(&patterns(&["s"]), false).is_match("ß".as_bytes()));
+    }
+
+    #[test]
+    fn empty_pattern_matches_every_line() {
+        let multi = MultiPattern::new(&patterns(&["zzz", ""]), true);
+
+        assert!(multi.is_match(b"anything"));
+        assert!(multi.find_all(b"anything").is_empty());
+        assert_eq!(vec![1], multi.patterns_in(b"anything"));
+    }
+}
@@ -0,0 +1,155 @@
+// Searches several files at once on a small pool of worker threads.
+// Workers pull the next file off a shared counter and send back the finished output for that file;
+// the calling thread holds results until every earlier file is done, so the output is always
+// in the same per-file order as a single-threaded search, however the threads get scheduled.
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+pub fn search_in_parallel<T, E, F, O>(
+    files: &[PathBuf],
+    threads: usize,
+    search: F,
+    mut emit: O,
+) -> Result<(), E>
+where
+    T: Send,
+    E: Send,
+    F: Fn(&Path) -> Result<T, E> + Sync,
+    O: FnMut(&Path, T) -> Result<(), E>,
+{
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+
+    // a scoped thread can borrow `files` and `search` from this stack frame, so there's no need
+    // to wrap them in an Arc (see concurrency) - the scope joins every worker before returning
+    thread::scope(|scope| {
+        for _ in 0..threads.min(files.len()) {
+            let tx = tx.clone();
+            let (next, search) = (&next, &search);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(file) = files.get(index) else { break };
+                // if the receiver is gone, the search already failed and nobody wants the rest
+                if tx.send((index, search(file))).is_err() {
+                    break;
+                }
+            });
+        }
+        // drop our own transmitter so the loop below ends once every worker has finished
+        drop(tx);
+
+        // results arrive in whatever order the threads finish; park them until it's their turn
+        let mut pending: Vec<Option<Result<T, E>>> = files.iter().map(|_| None).collect();
+        let mut flushed = 0;
+        for (index, result) in rx {
+            pending[index] = Some(result);
+            while let Some(result) = pending.get_mut(flushed).and_then(Option::take) {
+                // returning drops the receiver, which tells the workers to stop
+                emit(&files[flushed], result?)?;
+                flushed += 1;
+            }
+        }
+        Ok(())
+    })
+}
+
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{search_files, Config};
+    use std::fs;
+
+    fn config(threads: usize, paths: &[String]) -> Config {
+        let mut args = vec![
+            "cli-proj".to_string(),
+            "-j".to_string(),
+            threads.to_string(),
+        ];
+        args.push("needle".to_string());
+        args.extend_from_slice(paths);
+        Config::new(args.into_iter()).unwrap()
+    }
+
+    #[test]
+    fn order_matches_single_threaded_search() {
+        let dir = tempfile::tempdir().unwrap();
+        // files of very different sizes, so the workers finish them out of order
+        for i in 0..64 {
+            let mut contents = String::new();
+            for line in 0..(64 - i) * 200 {
+                if line % 97 == 0 {
+                    contents.push_str(&format!("needle {} in file {}\n", line, i));
+                } else {
+                    contents.push_str("hay hay hay hay hay hay hay hay\n");
+                }
+            }
+            fs::write(dir.path().join(format!("{:02}.log", i)), contents).unwrap();
+        }
+        let root = vec![dir.path().to_str().unwrap().to_string()];
+
+        let files = crate::walk::collect_files(&root, &config(1, &root).filter).unwrap();
+        let mut expected = Vec::new();
+        search_files(&config(1, &root), &files, true, &mut expected).unwrap();
+
+        for _ in 0..5 {
+            let mut out = Vec::new();
+            search_files(&config(8, &root), &files, true, &mut out).unwrap();
+
+            assert_eq!(expected, out);
+        }
+    }
+
+    #[test]
+    fn context_separators_between_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), "x\nneedle\ny\n").unwrap();
+        fs::write(dir.path().join("b"), "nothing\n").unwrap();
+        fs::write(dir.path().join("c"), "needle\n").unwrap();
+        let paths: Vec<String> = ["a", "b", "c"]
+            .iter()
+            .map(|f| dir.path().join(f).to_str().unwrap().to_string())
+            .collect();
+
+        let mut config = config(4, &paths);
+        config.after_context = 1;
+        let mut out = Vec::new();
+        search_files(
+            &config,
+            &crate::walk::collect_files(&paths, &config.filter).unwrap(),
+            false,
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!("needle\ny\n--\nneedle\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn unreadable_files_dont_stop_the_others() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), "needle a\n").unwrap();
+        // the gzip magic, then nothing a decoder can make sense of
+        fs::write(dir.path().join("b"), b"\x1f\x8b\x08\x00broken").unwrap();
+        fs::write(dir.path().join("d"), "needle d\n").unwrap();
+        // "c" was never created, like a file deleted between the walk and the search
+        let files: Vec<PathBuf> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|f| dir.path().join(f))
+            .collect();
+        let paths: Vec<String> = files.iter().map(|f| f.display().to_string()).collect();
+
+        for threads in [1, 4] {
+            let mut out = Vec::new();
+            let result = search_files(&config(threads, &paths), &files, false, &mut out);
+
+            assert_eq!("needle a\nneedle d\n", String::from_utf8(out).unwrap());
+            assert_eq!("2 files couldn't be read", result.unwrap_err().to_string());
+        }
+    }
+}
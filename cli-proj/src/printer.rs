@@ -0,0 +1,424 @@
+// Everything about how results look once they're found: path prefixes, line/column numbers,
+// context lines and the `--` separators between groups, or JSON records with --json.
+// run hands it one file at a time.
+use std::io::{self, Write};
+use std::ops::AddAssign;
+use std::path::Path;
+
+use crate::color::{self, paint};
+use crate::{context_groups, json, lines_with_offsets, walk, Match};
+
+// What gets printed for each file: its selected lines (the default), or just a summary of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputMode {
+    Lines,
+    // -c: the number of selected lines
+    Count,
+    // -l: the names of files with at least one selected line
+    FilesWithMatches,
+    // -L: the names of files without any
+    FilesWithoutMatch,
+}
+
+// Totals for a whole run, reported in the --json summary record.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub files_searched: usize,
+    pub files_with_matches: usize,
+    pub matched_lines: usize,
+}
+
+// so the per-thread totals of a parallel search can be summed with +=
+impl AddAssign for Stats {
+    fn add_assign(&mut self, other: Stats) {
+        self.files_searched += other.files_searched;
+        self.files_with_matches += other.files_with_matches;
+        self.matched_lines += other.matched_lines;
+    }
+}
+
+pub struct Printer<W: Write> {
+    out: W,
+    mode: OutputMode,
+    // prefix each line with the file it came from (when more than one file is searched)
+    show_path: bool,
+    column: bool,
+    json: bool,
+    // --color: whether to wrap matches, paths and numbers in ANSI colour codes
+    color: bool,
+    // --show-pattern: the patterns as given, to name the ones found on each matching line
+    patterns: Option<Vec<String>>,
+    // --text: print the lines of binary files too, instead of just saying they match
+    text: bool,
+    // whether the file being searched is binary (and they aren't being treated as text)
+    binary: bool,
+    // --sort-distance: the current file's lines (rendered) with their distance, until finish_file
+    ranked: Option<Vec<(usize, String)>>,
+    pub(crate) before_context: usize,
+    pub(crate) after_context: usize,
+    // grep only puts `--` *between* groups, so remember whether anything came before
+    printed_group: bool,
+    // --json only writes a "begin" record for files with something in them, just before their first line
+    begun: bool,
+    stats: Stats,
+}
+
+impl<W: Write> Printer<W> {
+    pub fn new(out: W, column: bool, before_context: usize, after_context: usize) -> Printer<W> {
+        Printer {
+            out,
+            mode: OutputMode::Lines,
+            show_path: false,
+            column,
+            json: false,
+            color: false,
+            patterns: None,
+            ranked: None,
+            text: false,
+            binary: false,
+            before_context,
+            after_context,
+            printed_group: false,
+            begun: false,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn with_mode(mut self, mode: OutputMode) -> Printer<W> {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_paths(mut self, show_path: bool) -> Printer<W> {
+        self.show_path = show_path;
+        self
+    }
+
+    pub fn with_json(mut self, json: bool) -> Printer<W> {
+        self.json = json;
+        self
+    }
+
+    pub fn with_color(mut self, color: bool) -> Printer<W> {
+        self.color = color;
+        self
+    }
+
+    pub fn with_patterns(mut self, patterns: &[String], show: bool) -> Printer<W> {
+        self.patterns = show.then(|| patterns.to_vec());
+        self
+    }
+
+    pub fn with_text(mut self, text: bool) -> Printer<W> {
+        self.text = text;
+        self
+    }
+
+    pub fn with_ranking(mut self, rank: bool) -> Printer<W> {
+        self.ranked = rank.then(Vec::new);
+        self
+    }
+
+    // Prints the matches of a whole file that's already in memory (see stream::search_reader for the line-by-line version).
+    pub fn print_file(&mut self, path: &Path, contents: &str, matches: &[Match]) -> io::Result<()> {
+        if !self.has_context() {
+            for m in matches {
+                self.print_match(path, m)?;
+            }
+            return Ok(());
+        }
+
+        let lines: Vec<(usize, &str)> = lines_with_offsets(contents).collect();
+        let groups = context_groups(
+            matches,
+            lines.len(),
+            self.before_context,
+            self.after_context,
+        );
+        // matches are sorted by line, so walk them alongside each group instead of searching
+        let mut matches = matches.iter().peekable();
+        for group in groups {
+            self.start_group()?;
+            for index in group {
+                match matches.next_if(|m| m.line_number == index + 1) {
+                    Some(m) => self.print_match(path, m)?,
+                    None => {
+                        let (byte_offset, line) = lines[index];
+                        self.print_context(path, index + 1, byte_offset, line.as_bytes())?
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // hands back the output, i.e. the buffer a worker thread printed a whole file into
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    // --follow prints as lines arrive, and stdout may be a pipe that only flushes when its buffer fills
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    // Called with the start of each file before it's searched. Like grep, a NUL byte in there makes it
+    // a binary file: its lines would only garble the terminal, so it's searched but only reported as
+    // "Binary file X matches". -c/-l/-L and JSON (which can carry any line) aren't affected.
+    pub(crate) fn start_file(&mut self, head: &[u8]) {
+        self.binary = !self.text && !self.json && walk::is_binary(head);
+    }
+
+    // only the default mode prints lines, -c/-l/-L print one summary per file from finish_file
+    pub(crate) fn prints_lines(&self) -> bool {
+        self.mode == OutputMode::Lines && !self.binary
+    }
+
+    // -l and -L know the answer as soon as one line is selected, there's no need to read the rest of the file,
+    // and neither does a binary file, since which lines matched isn't printed
+    pub(crate) fn stops_at_first_match(&self) -> bool {
+        match self.mode {
+            OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch => true,
+            OutputMode::Lines => self.binary,
+            OutputMode::Count => false,
+        }
+    }
+
+    pub(crate) fn has_context(&self) -> bool {
+        self.prints_lines() && (self.before_context > 0 || self.after_context > 0)
+    }
+
+    // Called once a file has been searched, with the number of lines selected in it.
+    // Prints the per-file summary for -c/-l/-L, and says whether the file counts as a "hit" for the exit status
+    // (for -L that's a file with no matches, since that's what gets listed).
+    pub(crate) fn finish_file(&mut self, path: &Path, count: usize) -> io::Result<bool> {
+        if let Some(ranked) = &mut self.ranked {
+            // a stable sort, so lines the same distance away stay in file order
+            ranked.sort_by_key(|&(distance, _)| distance);
+            for (_, record) in ranked.drain(..) {
+                writeln!(self.out, "{}", record)?;
+            }
+        }
+        self.stats += Stats {
+            files_searched: 1,
+            files_with_matches: (count > 0) as usize,
+            matched_lines: count,
+        };
+        if self.json {
+            if self.begun {
+                self.begun = false;
+                writeln!(
+                    self.out,
+                    r#"{{"type":"end","data":{{"path":{},"stats":{{"matched_lines":{}}}}}}}"#,
+                    json::path(path),
+                    count
+                )?;
+            }
+            return Ok(count > 0);
+        }
+
+        match self.mode {
+            OutputMode::Lines => {
+                if self.binary && count > 0 {
+                    writeln!(
+                        self.out,
+                        "Binary file {} matches",
+                        self.style(color::PATH, &path.display().to_string())
+                    )?;
+                }
+                Ok(count > 0)
+            }
+            OutputMode::Count => {
+                writeln!(self.out, "{}{}", self.prefix(path, ':'), count)?;
+                Ok(count > 0)
+            }
+            OutputMode::FilesWithMatches => {
+                if count > 0 {
+                    writeln!(
+                        self.out,
+                        "{}",
+                        self.style(color::PATH, &path.display().to_string())
+                    )?;
+                }
+                Ok(count > 0)
+            }
+            OutputMode::FilesWithoutMatch => {
+                if count == 0 {
+                    writeln!(
+                        self.out,
+                        "{}",
+                        self.style(color::PATH, &path.display().to_string())
+                    )?;
+                }
+                Ok(count == 0)
+            }
+        }
+    }
+
+    // the last record of a --json run, with the totals over every file (nothing at all for the text output)
+    pub fn print_summary(&mut self, stats: Stats) -> io::Result<()> {
+        if !self.json {
+            return Ok(());
+        }
+        writeln!(
+            self.out,
+            r#"{{"type":"summary","data":{{"stats":{{"files_searched":{},"files_with_matches":{},"matched_lines":{}}}}}}}"#,
+            stats.files_searched, stats.files_with_matches, stats.matched_lines
+        )
+    }
+
+    // called before the first line of every group of context, prints the `--` between groups
+    pub(crate) fn start_group(&mut self) -> io::Result<()> {
+        // JSON records carry their own line numbers, so there's no separator to print
+        if self.printed_group && !self.json {
+            writeln!(self.out, "{}", self.style(color::SEPARATOR, "--"))?;
+        }
+        self.printed_group = true;
+        Ok(())
+    }
+
+    pub(crate) fn print_match(&mut self, path: &Path, m: &Match) -> io::Result<()> {
+        let record = if self.json {
+            self.begin(path)?;
+            self.json_match(path, m)
+        } else {
+            self.text_match(path, m)
+        };
+        // --sort-distance holds a file's lines back until finish_file, when they can be put in order
+        if let (Some(ranked), Some(distance)) = (&mut self.ranked, m.distance) {
+            ranked.push((distance, record));
+            return Ok(());
+        }
+        writeln!(self.out, "{}", record)
+    }
+
+    fn text_match(&self, path: &Path, m: &Match) -> String {
+        let mut prefix = self.prefix(path, ':');
+        if self.column {
+            // path:line:col:text is what editors (vim's quickfix, VS Code's terminal links) know how to jump to.
+            // The column is 1-based, counted in bytes of the line as it is in the file, like grep/ripgrep do.
+            let col = m.columns.first().map_or(1, |c| c.start + 1);
+            prefix.push_str(&self.number(m.line_number, ':'));
+            prefix.push_str(&self.number(col, ':'));
+        }
+        if let Some(patterns) = &self.patterns {
+            let found: Vec<&str> = m
+                .patterns
+                .iter()
+                .filter_map(|&i| patterns.get(i).map(String::as_str))
+                .collect();
+            prefix.push_str(&found.join(","));
+            prefix.push_str(&self.style(color::SEPARATOR, ":"));
+        }
+        if self.color {
+            format!("{}{}", prefix, color::highlight(m.line, &m.columns))
+        } else {
+            format!("{}{}", prefix, String::from_utf8_lossy(m.line))
+        }
+    }
+
+    // context lines use '-' instead of ':' after the prefix, the same way grep tells them apart
+    pub(crate) fn print_context(
+        &mut self,
+        path: &Path,
+        line_number: usize,
+        byte_offset: usize,
+        line: &[u8],
+    ) -> io::Result<()> {
+        if self.json {
+            self.begin(path)?;
+            return writeln!(
+                self.out,
+                r#"{{"type":"context","data":{{"path":{},"lines":{},"line_number":{},"absolute_offset":{},"submatches":[]}}}}"#,
+                json::path(path),
+                json::data(line),
+                line_number,
+                byte_offset
+            );
+        }
+        let mut prefix = self.prefix(path, '-');
+        if self.column {
+            prefix.push_str(&self.number(line_number, '-'));
+        }
+        writeln!(self.out, "{}{}", prefix, String::from_utf8_lossy(line))
+    }
+
+    fn prefix(&self, path: &Path, separator: char) -> String {
+        if self.show_path {
+            format!(
+                "{}{}",
+                self.style(color::PATH, &path.display().to_string()),
+                self.style(color::SEPARATOR, &separator.to_string())
+            )
+        } else {
+            String::new()
+        }
+    }
+
+    // a line or column number followed by its separator
+    fn number(&self, n: usize, separator: char) -> String {
+        format!(
+            "{}{}",
+            self.style(color::LINE_NUMBER, &n.to_string()),
+            self.style(color::SEPARATOR, &separator.to_string())
+        )
+    }
+
+    fn style(&self, style: &str, text: &str) -> String {
+        if self.color {
+            paint(style, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn begin(&mut self, path: &Path) -> io::Result<()> {
+        if self.begun {
+            return Ok(());
+        }
+        self.begun = true;
+        writeln!(
+            self.out,
+            r#"{{"type":"begin","data":{{"path":{}}}}}"#,
+            json::path(path)
+        )
+    }
+
+    // One line of JSON per matching line, modelled on ripgrep's --json "match" message:
+    // the line, where it is, and each hit as a byte range into the line along with the text it covers.
+    // "patterns" lists which of the patterns (by position, starting at 0) were found on the line.
+    // With --fuzzy there's also the "distance" of the line from the query.
+    fn json_match(&self, path: &Path, m: &Match) -> String {
+        let submatches: Vec<String> = m
+            .columns
+            .iter()
+            .map(|c| {
+                format!(
+                    r#"{{"match":{},"start":{},"end":{}}}"#,
+                    json::data(&m.line[c.clone()]),
+                    c.start,
+                    c.end
+                )
+            })
+            .collect();
+        let patterns: Vec<String> = m.patterns.iter().map(usize::to_string).collect();
+        let distance = m
+            .distance
+            .map_or(String::new(), |d| format!(r#","distance":{}"#, d));
+        format!(
+            r#"{{"type":"match","data":{{"path":{},"lines":{},"line_number":{},"column":{},"absolute_offset":{},"submatches":[{}],"patterns":[{}]{}}}}}"#,
+            json::path(path),
+            json::data(m.line),
+            m.line_number,
+            m.columns.first().map_or(1, |c| c.start + 1),
+            m.byte_offset,
+            submatches.join(","),
+            patterns.join(","),
+            distance
+        )
+    }
+}
@@ -0,0 +1,445 @@
+// --replace: rewrite every match with a replacement string. By default nothing on disk changes,
+// the edits are shown as a unified diff (the same format as `diff -u` and `git diff`, so it can be
+// reviewed or piped to `patch`). With --write each changed file is rewritten atomically: the new
+// contents go to a temporary file next to it, which is then renamed over the original, so a crash
+// or a full disk never leaves a half-written file behind.
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::color::{self, paint};
+use crate::decompress::Compression;
+use crate::{merge_windows, walk, Config, Matcher};
+
+// lines of unchanged context around each change in the diff, the same as diff -u
+const DIFF_CONTEXT: usize = 3;
+
+// The result of replacing in one file: every line (terminators included) before and after.
+// Lines are replaced one at a time, so the i-th old line became the i-th new one; a replacement
+// containing "\n" makes a new "line" that is really several.
+pub struct Replaced<'a> {
+    pub old: Vec<&'a str>,
+    pub new: Vec<String>,
+    // indexes of the lines that changed
+    pub changed: Vec<usize>,
+}
+
+impl Replaced<'_> {
+    pub fn contents(&self) -> String {
+        self.new.concat()
+    }
+}
+
+pub fn replace_in<'a>(matcher: &Matcher, replacement: &str, contents: &'a str) -> Replaced<'a> {
+    let old: Vec<&str> = contents.split_inclusive('\n').collect();
+    let mut new = Vec::with_capacity(old.len());
+    let mut changed = Vec::new();
+    for (index, line) in old.iter().enumerate() {
+        // the same line the search sees, so the terminator ("\n" or "\r\n") is kept as it was
+        let (body, terminator) = split_terminator(line);
+        if matcher.is_match(body.as_bytes()) {
+            let replaced = matcher.replace_all(body.as_bytes(), replacement);
+            // a byte-level regex ((?-u:\xff)) can split a char, and the result of that isn't text
+            // any more: the line is left as it was, like the files that aren't UTF-8 to begin with
+            if let Ok(replaced) = std::str::from_utf8(&replaced) {
+                if replaced != body {
+                    changed.push(index);
+                    new.push(format!("{}{}", replaced, terminator));
+                    continue;
+                }
+            }
+        }
+        new.push(line.to_string());
+    }
+    Replaced { old, new, changed }
+}
+
+fn split_terminator(line: &str) -> (&str, &str) {
+    let body = line.strip_suffix('\n').unwrap_or(line);
+    let body = body.strip_suffix('\r').unwrap_or(body);
+    (body, &line[body.len()..])
+}
+
+pub fn unified_diff(path: &Path, replaced: &Replaced, color: bool) -> String {
+    let style = |style: &'static str| color.then_some(style);
+    // like git, an absolute path goes after a/ and b/ without its leading / (never a//tmp/x)
+    let shown: PathBuf = path
+        .components()
+        .filter(|c| !matches!(c, Component::Prefix(_) | Component::RootDir))
+        .collect();
+    let mut out = String::new();
+    out.push_str(&diff_lines(
+        "--- a/",
+        &format!("{}\n", shown.display()),
+        style(color::PATH),
+    ));
+    out.push_str(&diff_lines(
+        "+++ b/",
+        &format!("{}\n", shown.display()),
+        style(color::PATH),
+    ));
+
+    let hunks = merge_windows(
+        replaced.changed.iter().copied(),
+        replaced.old.len(),
+        DIFF_CONTEXT,
+        DIFF_CONTEXT,
+    );
+    // how many more (or fewer) lines the new file has than the old one, before the current hunk
+    let mut shift: isize = 0;
+    for hunk in hunks {
+        let mut body = String::new();
+        let mut new_count = 0;
+        let mut index = hunk.start;
+        while index < hunk.end {
+            if replaced.changed.binary_search(&index).is_err() {
+                body.push_str(&diff_lines(" ", replaced.old[index], None));
+                new_count += 1;
+                index += 1;
+                continue;
+            }
+            // a run of changed lines is shown like diff does: all the removed lines, then all the added ones
+            let run_end = (index..hunk.end)
+                .find(|i| replaced.changed.binary_search(i).is_err())
+                .unwrap_or(hunk.end);
+            for old in &replaced.old[index..run_end] {
+                body.push_str(&diff_lines("-", old, style(color::MATCH)));
+            }
+            for new in &replaced.new[index..run_end] {
+                body.push_str(&diff_lines("+", new, style(color::LINE_NUMBER)));
+                new_count += new.split_inclusive('\n').count();
+            }
+            index = run_end;
+        }
+        let old_start = hunk.start + 1;
+        let new_start = (old_start as isize + shift) as usize;
+        let header = format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start,
+            hunk.len(),
+            new_start,
+            new_count
+        );
+        out.push_str(&diff_lines("", &header, style(color::SEPARATOR)));
+        out.push_str(&body);
+        shift += new_count as isize - hunk.len() as isize;
+    }
+    out
+}
+
+// One or more lines of a diff, each starting with `marker`, coloured line by line when there's a style.
+// Like diff, a last line without a newline at the end gets the "\ No newline at end of file" note
+// so patch can reproduce it exactly.
+fn diff_lines(marker: &str, text: &str, style: Option<&str>) -> String {
+    let mut out = String::new();
+    for line in text.split_inclusive('\n') {
+        let line = format!("{}{}", marker, line.strip_suffix('\n').unwrap_or(line));
+        match style {
+            Some(style) => out.push_str(&paint(style, &line)),
+            None => out.push_str(&line),
+        }
+        out.push('\n');
+    }
+    if !text.ends_with('\n') {
+        out.push_str("\\ No newline at end of file\n");
+    }
+    out
+}
+
+// Writes the new contents to a temporary file in the same directory (rename is only atomic within
+// one filesystem), with the original's permissions, then renames it over the original. A symlink is
+// followed first: renaming over the link itself would replace it with a regular file and leave the
+// file it points to unchanged.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let target = match fs::canonicalize(path) {
+        Ok(target) => target,
+        // nothing there yet, so nothing to follow
+        Err(e) if e.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+        Err(e) => return Err(e),
+    };
+    let path = target.as_path();
+    let dir = path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a file", path.display()),
+        )
+    })?;
+    let (temp, mut file) = create_temp(dir, name)?;
+
+    let result = (|| {
+        file.write_all(contents)?;
+        // make sure the data is really on disk before the rename makes it the file everyone sees
+        file.sync_all()?;
+        // keep the permissions of the file being replaced, if there is one
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&temp, metadata.permissions())?;
+        }
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        // best effort, the original is untouched either way
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+// create_new never opens a file that's already there, so whatever is left at a temporary name (a
+// stale file, or a symlink to somewhere else) is never written through; the next name is tried instead.
+fn create_temp(dir: &Path, name: &OsStr) -> io::Result<(PathBuf, File)> {
+    let mut attempt = 0;
+    loop {
+        let temp = dir.join(temp_name(name, attempt));
+        match OpenOptions::new().write(true).create_new(true).open(&temp) {
+            Ok(file) => return Ok((temp, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn temp_name(name: &OsStr, attempt: usize) -> String {
+    format!(
+        ".{}.cli-proj-{}-{}.tmp",
+        name.to_string_lossy(),
+        std::process::id(),
+        attempt
+    )
+}
+
+// What run does instead of printing matches when --replace is given.
+pub(crate) fn replace_files<W: Write>(
+    config: &Config,
+    replacement: &str,
+    files: &[PathBuf],
+    mut out: W,
+) -> io::Result<bool> {
+    let matcher = config.matcher();
+    let mut any = false;
+    for file in files {
+        let bytes = if file == Path::new("-") {
+            let mut bytes = Vec::new();
+            io::stdin().lock().read_to_end(&mut bytes)?;
+            bytes
+        } else {
+            fs::read(file)?
+        };
+        // a compressed file can't be edited in place, the replacement would have to be recompressed
+        if walk::is_binary(&bytes) || Compression::detect(&bytes).is_some() {
+            continue;
+        }
+        // and a file that isn't UTF-8 would come back with its odd bytes replaced, so it's left alone
+        let Ok(contents) = String::from_utf8(bytes) else {
+            continue;
+        };
+
+        let replaced = replace_in(&matcher, replacement, &contents);
+        if replaced.changed.is_empty() {
+            continue;
+        }
+        any = true;
+        if config.write {
+            write_atomically(file, replaced.contents().as_bytes())?;
+            writeln!(
+                out,
+                "{}: {} lines changed",
+                file.display(),
+                replaced.changed.len()
+            )?;
+        } else {
+            write!(out, "{}", unified_diff(file, &replaced, config.color))?;
+        }
+    }
+    Ok(any)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::bytes::Regex;
+
+    const CONTENTS: &str = "\
+Rust:
+safe, fast, productive.
+Pick three.
+one
+two
+three
+four
+five
+six
+Duct tape, productive.";
+
+    #[test]
+    fn replaces_literal_matches() {
+        let replaced = replace_in(&Matcher::literal("duct", true), "DUCT", CONTENTS);
+
+        assert_eq!(vec![1, 9], replaced.changed);
+        assert_eq!(
+            CONTENTS.replace("productive", "proDUCTive"),
+            replaced.contents()
+        );
+    }
+
+    #[test]
+    fn regex_capture_groups() {
+        let matcher = Matcher::Regex(Regex::new(r"(\w+), (\w+)").unwrap());
+        let replaced = replace_in(&matcher, "$2 & ${1}", "safe, fast\r\nnothing\n");
+
+        assert_eq!("fast & safe\r\nnothing\n", replaced.contents());
+    }
+
+    #[test]
+    fn case_insensitive_replacement() {
+        let replaced = replace_in(
+            &Matcher::literal("RUST", false),
+            "Ferris",
+            "Rust and rust\n",
+        );
+
+        assert_eq!("Ferris and Ferris\n", replaced.contents());
+    }
+
+    #[test]
+    fn diff_preview() {
+        let replaced = replace_in(&Matcher::literal("duct", true), "DUCT", CONTENTS);
+
+        assert_eq!(
+            "\
+--- a/poem.txt
++++ b/poem.txt
+@@ -1,5 +1,5 @@
+ Rust:
+-safe, fast, productive.
++safe, fast, proDUCTive.
+ Pick three.
+ one
+ two
+@@ -7,4 +7,4 @@
+ four
+ five
+ six
+-Duct tape, productive.
+\\ No newline at end of file
++Duct tape, proDUCTive.
+\\ No newline at end of file
+",
+            unified_diff(Path::new("poem.txt"), &replaced, false)
+        );
+    }
+
+    #[test]
+    fn diff_groups_consecutive_changes() {
+        let replaced = replace_in(&Matcher::literal("x", true), "y", "a\nx1\nx2\nb\n");
+
+        assert_eq!(
+            "\
+--- a/f
++++ b/f
+@@ -1,4 +1,4 @@
+ a
+-x1
+-x2
++y1
++y2
+ b
+",
+            unified_diff(Path::new("f"), &replaced, false)
+        );
+    }
+
+    #[test]
+    fn diff_with_multiline_replacement() {
+        let replaced = replace_in(&Matcher::literal("b", true), "b\nb2", "a\nb\nc\nb\nd\n");
+
+        assert_eq!(
+            "\
+--- a/f
++++ b/f
+@@ -1,5 +1,7 @@
+ a
+-b
++b
++b2
+ c
+-b
++b
++b2
+ d
+",
+            unified_diff(Path::new("f"), &replaced, false)
+        );
+    }
+
+    #[test]
+    fn diff_of_an_absolute_path() {
+        let replaced = replace_in(&Matcher::literal("x", true), "y", "x\n");
+
+        assert!(unified_diff(Path::new("/tmp/x"), &replaced, false)
+            .starts_with("--- a/tmp/x\n+++ b/tmp/x\n"));
+    }
+
+    #[test]
+    fn writes_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("poem.txt");
+        fs::write(&path, CONTENTS).unwrap();
+
+        let replaced = replace_in(&Matcher::literal("duct", true), "DUCT", CONTENTS);
+        write_atomically(&path, replaced.contents().as_bytes()).unwrap();
+
+        assert_eq!(replaced.contents(), fs::read_to_string(&path).unwrap());
+        // nothing left behind but the file itself
+        assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_through_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real.txt");
+        let link = dir.path().join("link.txt");
+        fs::write(&real, "old\n").unwrap();
+        fs::set_permissions(&real, fs::Permissions::from_mode(0o640)).unwrap();
+        symlink(&real, &link).unwrap();
+
+        write_atomically(&link, b"new\n").unwrap();
+
+        // the link is still a link, and the file it points to has the new contents and its old mode
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!("new\n", fs::read_to_string(&real).unwrap());
+        assert_eq!(
+            0o640,
+            fs::metadata(&real).unwrap().permissions().mode() & 0o777
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn never_writes_through_whatever_has_the_temporary_name() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("poem.txt");
+        let other = dir.path().join("other.txt");
+        fs::write(&path, "old\n").unwrap();
+        fs::write(&other, "other\n").unwrap();
+        let planted = dir.path().join(temp_name(OsStr::new("poem.txt"), 0));
+        symlink(&other, &planted).unwrap();
+
+        write_atomically(&path, b"new\n").unwrap();
+
+        assert_eq!("new\n", fs::read_to_string(&path).unwrap());
+        assert_eq!("other\n", fs::read_to_string(&other).unwrap());
+        assert!(fs::symlink_metadata(&planted).is_ok());
+    }
+}
@@ -0,0 +1,507 @@
+// The library side of cli-proj, for programs that want to search without spawning a process.
+// Config::new only understands command line arguments and run prints to stdout; here the search is
+// put together with a builder and the results go to whatever Sink the caller passes in:
+//
+//     let searcher = SearcherBuilder::new().pattern("timeout").case_insensitive(true).build()?;
+//     searcher.search_path("./logs", &mut |path: &Path, m: &Match| {
+//         println!("{}:{}: {}", path.display(), m.line_number, String::from_utf8_lossy(m.line));
+//         Ok(true)
+//     })?;
+//
+// Underneath it's the same search as the command line (the same Matcher, directory walk,
+// decompression and line-by-line reading), only the output is different.
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use crate::stream::{search_lines, LineOutput};
+use crate::{
+    compile_patterns, decompress, walk, Boundary, Config, Filter, Match, Matcher, OutputMode,
+    ParseError, Syntax,
+};
+
+// Receives what a Searcher finds. Only `matched` has to be written, the rest do nothing by default.
+pub trait Sink {
+    // A selected line. Returning false stops the search of this file (the next file is still searched).
+    fn matched(&mut self, path: &Path, m: &Match) -> io::Result<bool>;
+
+    // a line around a match, with SearcherBuilder::context
+    fn context(
+        &mut self,
+        _path: &Path,
+        _line_number: usize,
+        _byte_offset: usize,
+        _line: &[u8],
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    // called once a file has been searched, with the number of lines selected in it
+    fn finish(&mut self, _path: &Path, _count: usize) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// so a closure can be a Sink when only the matches are wanted
+impl<F: FnMut(&Path, &Match) -> io::Result<bool>> Sink for F {
+    fn matched(&mut self, path: &Path, m: &Match) -> io::Result<bool> {
+        self(path, m)
+    }
+}
+
+#[derive(Debug)]
+pub enum SearchError {
+    // build() was called without a single pattern
+    NoPattern,
+    // two settings that can't be used together, named after the command line options they stand for
+    Conflict(String, String),
+    InvalidRegex(regex::Error),
+    InvalidGlob(String),
+    // any other setting the command line would turn down, as it would put it
+    Invalid(ParseError),
+    // a file or directory couldn't be read (or the Sink failed while handling it)
+    Io { path: PathBuf, error: io::Error },
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SearchError::NoPattern => write!(f, "nothing to search for"),
+            SearchError::Conflict(a, b) => write!(f, "{} can't be used with {}", a, b),
+            SearchError::InvalidRegex(e) => write!(f, "invalid regular expression: {}", e),
+            SearchError::InvalidGlob(glob) => write!(f, "invalid glob {:?}", glob),
+            SearchError::Invalid(e) => write!(f, "{}", e),
+            SearchError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+        }
+    }
+}
+
+impl Error for SearchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SearchError::InvalidRegex(e) => Some(e),
+            SearchError::Invalid(e) => Some(e),
+            SearchError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+// Everything defaults to what the command line does without options: a case-sensitive
+// literal search of each file, skipping what ignore files list.
+#[derive(Debug, Clone)]
+pub struct SearcherBuilder {
+    patterns: Vec<String>,
+    regex: bool,
+    case_sensitive: bool,
+    boundary: Option<Boundary>,
+    fuzzy: Option<usize>,
+    only: Vec<Syntax>,
+    invert: bool,
+    before_context: usize,
+    after_context: usize,
+    globs: Vec<String>,
+    excludes: Vec<String>,
+    respect_ignore: bool,
+    binary_detection: bool,
+}
+
+impl Default for SearcherBuilder {
+    fn default() -> SearcherBuilder {
+        SearcherBuilder {
+            patterns: Vec::new(),
+            regex: false,
+            case_sensitive: true,
+            boundary: None,
+            fuzzy: None,
+            only: Vec::new(),
+            invert: false,
+            before_context: 0,
+            after_context: 0,
+            globs: Vec::new(),
+            excludes: Vec::new(),
+            respect_ignore: true,
+            binary_detection: true,
+        }
+    }
+}
+
+impl SearcherBuilder {
+    pub fn new() -> SearcherBuilder {
+        SearcherBuilder::default()
+    }
+
+    // like -e, can be called several times to select lines matching any of them
+    pub fn pattern(mut self, pattern: impl Into<String>) -> SearcherBuilder {
+        self.patterns.push(pattern.into());
+        self
+    }
+
+    // -E
+    pub fn regex(mut self, regex: bool) -> SearcherBuilder {
+        self.regex = regex;
+        self
+    }
+
+    // -i
+    pub fn case_insensitive(mut self, yes: bool) -> SearcherBuilder {
+        self.case_sensitive = !yes;
+        self
+    }
+
+    // -w/-x
+    pub fn boundary(mut self, boundary: Boundary) -> SearcherBuilder {
+        self.boundary = Some(boundary);
+        self
+    }
+
+    // --fuzzy
+    pub fn fuzzy(mut self, max_distance: usize) -> SearcherBuilder {
+        self.fuzzy = Some(max_distance);
+        self
+    }
+
+    // --only, can be called several times; every file is then taken to be Rust source
+    pub fn only(mut self, syntax: Syntax) -> SearcherBuilder {
+        self.only.push(syntax);
+        self
+    }
+
+    // -v
+    pub fn invert(mut self, invert: bool) -> SearcherBuilder {
+        self.invert = invert;
+        self
+    }
+
+    // -B/-A, the lines go to Sink::context
+    pub fn context(mut self, before: usize, after: usize) -> SearcherBuilder {
+        self.before_context = before;
+        self.after_context = after;
+        self
+    }
+
+    // --glob and --exclude, for the files found in directories
+    pub fn glob(mut self, glob: impl Into<String>) -> SearcherBuilder {
+        self.globs.push(glob.into());
+        self
+    }
+
+    pub fn exclude(mut self, glob: impl Into<String>) -> SearcherBuilder {
+        self.excludes.push(glob.into());
+        self
+    }
+
+    // false is --no-ignore
+    pub fn respect_ignore(mut self, yes: bool) -> SearcherBuilder {
+        self.respect_ignore = yes;
+        self
+    }
+
+    // Like the command line, a file with a NUL byte near the start is taken to be binary: its lines
+    // aren't passed to the Sink (they'd be garbage), the search stops at the first selected line, and
+    // Sink::finish says whether there was one. false is --text, every file is searched as text.
+    pub fn binary_detection(mut self, yes: bool) -> SearcherBuilder {
+        self.binary_detection = yes;
+        self
+    }
+
+    // Checks the settings the same way Config::new checks the command line, and compiles the patterns.
+    pub fn build(self) -> Result<Searcher, SearchError> {
+        if self.patterns.is_empty() {
+            return Err(SearchError::NoPattern);
+        }
+        let (regex, regex_set) = compile_patterns(
+            &self.patterns,
+            self.regex,
+            self.case_sensitive,
+            self.boundary,
+            self.fuzzy,
+        )
+        .map_err(|e| match e {
+            ParseError::ConflictingOptions(a, b) => SearchError::Conflict(a, b),
+            ParseError::InvalidRegex(e) => SearchError::InvalidRegex(e),
+            e => SearchError::Invalid(e),
+        })?;
+        let filter = Filter::new(&self.globs, &self.excludes, self.respect_ignore)
+            .map_err(SearchError::InvalidGlob)?;
+        // the rest of Config is about how the command line prints, which a Sink decides for itself
+        let config = Config {
+            paths: Vec::new(),
+            patterns: self.patterns,
+            case_sensitive: self.case_sensitive,
+            regex,
+            regex_set,
+            show_pattern: false,
+            text: !self.binary_detection,
+            use_index: false,
+            follow: false,
+            only: self.only,
+            boundary: self.boundary,
+            fuzzy: self.fuzzy,
+            sort_distance: false,
+            column: false,
+            before_context: self.before_context,
+            after_context: self.after_context,
+            threads: 1,
+            invert: self.invert,
+            mode: OutputMode::Lines,
+            json: false,
+            color: false,
+            filter,
+            replace: None,
+            write: false,
+        };
+        Ok(Searcher {
+            matcher: config.matcher(),
+            config,
+        })
+    }
+}
+
+pub struct Searcher {
+    config: Config,
+    matcher: Matcher,
+}
+
+impl Searcher {
+    // Searches a file, or every file under a directory (walked the way the command line walks it),
+    // with "-" meaning stdin. Returns the number of lines selected in all of them.
+    pub fn search_path<S: Sink + ?Sized>(
+        &self,
+        path: impl AsRef<Path>,
+        sink: &mut S,
+    ) -> Result<usize, SearchError> {
+        let path = path.as_ref();
+        let io_error = |path: &Path| {
+            let path = path.to_path_buf();
+            move |error| SearchError::Io { path, error }
+        };
+        let files =
+            walk::collect_files(&[path.to_string_lossy().into_owned()], &self.config.filter)
+                .map_err(io_error(path))?;
+        let mut count = 0;
+        for file in files {
+            count += if file == Path::new("-") {
+                self.search_reader(Path::new("(standard input)"), io::stdin().lock(), sink)?
+            } else {
+                let reader = File::open(&file).map_err(io_error(&file))?;
+                self.search_reader(&file, BufReader::new(reader), sink)?
+            };
+        }
+        Ok(count)
+    }
+
+    // Searches anything readable (compressed or not), reporting its lines as coming from `path`.
+    pub fn search_reader<R: BufRead, S: Sink + ?Sized>(
+        &self,
+        path: &Path,
+        reader: R,
+        sink: &mut S,
+    ) -> Result<usize, SearchError> {
+        let mut output = SinkOutput {
+            sink,
+            before_context: self.config.before_context,
+            after_context: self.config.after_context,
+            binary: false,
+            stopped: false,
+        };
+        let search = || -> io::Result<usize> {
+            let mut reader = decompress::reader(reader)?;
+            // the same check as Printer::start_file, on the first chunk of the (decompressed) file
+            output.binary = !self.config.text && walk::is_binary(reader.fill_buf()?);
+            let count = search_lines(&self.matcher, reader, &mut output, path)?;
+            output.sink.finish(path, count)?;
+            Ok(count)
+        };
+        search().map_err(|error| SearchError::Io {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+}
+
+// what search_lines reports to when the lines go to a Sink
+struct SinkOutput<'s, S: Sink + ?Sized> {
+    sink: &'s mut S,
+    before_context: usize,
+    after_context: usize,
+    // the file is binary, only whether it matches is reported
+    binary: bool,
+    // the Sink said it had seen enough of this file
+    stopped: bool,
+}
+
+impl<S: Sink + ?Sized> LineOutput for SinkOutput<'_, S> {
+    fn before_context(&self) -> usize {
+        self.before_context
+    }
+
+    fn after_context(&self) -> usize {
+        self.after_context
+    }
+
+    fn stops_at_first_match(&self) -> bool {
+        self.stopped || self.binary
+    }
+
+    fn prints_lines(&self) -> bool {
+        !self.binary
+    }
+
+    // a Sink can tell where a group starts from the line numbers
+    fn start_group(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn print_context(
+        &mut self,
+        path: &Path,
+        line_number: usize,
+        byte_offset: usize,
+        line: &[u8],
+    ) -> io::Result<()> {
+        self.sink.context(path, line_number, byte_offset, line)
+    }
+
+    fn print_match(&mut self, path: &Path, m: &Match) -> io::Result<()> {
+        self.stopped = !self.sink.matched(path, m)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // every selected line as (line number, line), and the context lines as (line number, "-line")
+    #[derive(Default)]
+    struct Lines(Vec<(usize, String)>);
+
+    impl Sink for Lines {
+        fn matched(&mut self, _: &Path, m: &Match) -> io::Result<bool> {
+            self.0
+                .push((m.line_number, String::from_utf8_lossy(m.line).into_owned()));
+            Ok(true)
+        }
+
+        fn context(
+            &mut self,
+            _: &Path,
+            line_number: usize,
+            _: usize,
+            line: &[u8],
+        ) -> io::Result<()> {
+            self.0
+                .push((line_number, format!("-{}", String::from_utf8_lossy(line))));
+            Ok(())
+        }
+    }
+
+    const LOG: &str = "boot\nTimeout: disk\nok\ntimeout: net\nok\n";
+
+    #[test]
+    fn searches_into_a_sink() {
+        let searcher = SearcherBuilder::new()
+            .pattern("timeout")
+            .case_insensitive(true)
+            .context(0, 1)
+            .build()
+            .unwrap();
+        let mut lines = Lines::default();
+
+        let count = searcher
+            .search_reader(Path::new("log"), LOG.as_bytes(), &mut lines)
+            .unwrap();
+        assert_eq!(2, count);
+        assert_eq!(
+            vec![
+                (2, "Timeout: disk".to_string()),
+                (3, "-ok".to_string()),
+                (4, "timeout: net".to_string()),
+                (5, "-ok".to_string()),
+            ],
+            lines.0
+        );
+    }
+
+    #[test]
+    fn closure_sink_can_stop_early() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.log"), LOG).unwrap();
+        fs::write(dir.path().join("b.log"), LOG).unwrap();
+        let searcher = SearcherBuilder::new().pattern("ok").build().unwrap();
+
+        // only the first match of each file is wanted
+        let mut found = Vec::new();
+        let count = searcher
+            .search_path(dir.path(), &mut |path: &Path, m: &Match| {
+                found.push((path.file_name().unwrap().to_owned(), m.line_number));
+                Ok(false)
+            })
+            .unwrap();
+        found.sort();
+        assert_eq!(2, count);
+        assert_eq!(vec![("a.log".into(), 3), ("b.log".into(), 3)], found);
+    }
+
+    #[test]
+    fn binary_files_like_the_command_line() {
+        let binary = b"\x7fELF\0\nneedle\nneedle\n";
+        let search = |searcher: Searcher| {
+            let mut lines = Lines::default();
+            let count = searcher
+                .search_reader(Path::new("a.out"), &binary[..], &mut lines)
+                .unwrap();
+            (count, lines.0.len())
+        };
+
+        // it matches, but no garbage lines get to the Sink
+        assert_eq!(
+            (1, 0),
+            search(SearcherBuilder::new().pattern("needle").build().unwrap())
+        );
+        assert_eq!(
+            (2, 2),
+            search(
+                SearcherBuilder::new()
+                    .pattern("needle")
+                    .binary_detection(false)
+                    .build()
+                    .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn structured_errors() {
+        assert!(matches!(
+            SearcherBuilder::new().build(),
+            Err(SearchError::NoPattern)
+        ));
+        assert!(matches!(
+            SearcherBuilder::new().pattern("(").regex(true).build(),
+            Err(SearchError::InvalidRegex(_))
+        ));
+        assert!(matches!(
+            SearcherBuilder::new()
+                .pattern("a")
+                .regex(true)
+                .fuzzy(1)
+                .build(),
+            Err(SearchError::Conflict(..))
+        ));
+
+        let searcher = SearcherBuilder::new().pattern("a").build().unwrap();
+        let missing = Path::new("no/such/file");
+        match searcher.search_path(missing, &mut |_: &Path, _: &Match| Ok(true)) {
+            Err(SearchError::Io { path, error }) => {
+                assert_eq!(missing, path);
+                assert_eq!(io::ErrorKind::NotFound, error.kind());
+            }
+            _ => panic!("expected an io error"),
+        }
+    }
+}
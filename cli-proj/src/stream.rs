@@ -0,0 +1,262 @@
+// Line-by-line search over any BufRead (a file, stdin, ...), so memory use stays bounded
+// by the longest line plus the -B context lines, instead of the size of the whole input.
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use crate::printer::Printer;
+use crate::{Match, Matcher};
+
+// Where search_lines reports what it finds: the Printer for the command line, or a library
+// caller's Sink (see searcher.rs).
+pub(crate) trait LineOutput {
+    fn before_context(&self) -> usize;
+    fn after_context(&self) -> usize;
+    // true once nothing more of the file needs to be read (the checks come after each selected line)
+    fn stops_at_first_match(&self) -> bool;
+    fn prints_lines(&self) -> bool;
+    fn start_group(&mut self) -> io::Result<()>;
+    fn print_context(
+        &mut self,
+        path: &Path,
+        line_number: usize,
+        byte_offset: usize,
+        line: &[u8],
+    ) -> io::Result<()>;
+    fn print_match(&mut self, path: &Path, m: &Match) -> io::Result<()>;
+}
+
+impl<W: Write> LineOutput for Printer<W> {
+    fn before_context(&self) -> usize {
+        self.before_context
+    }
+
+    fn after_context(&self) -> usize {
+        self.after_context
+    }
+
+    fn stops_at_first_match(&self) -> bool {
+        Printer::stops_at_first_match(self)
+    }
+
+    fn prints_lines(&self) -> bool {
+        Printer::prints_lines(self)
+    }
+
+    fn start_group(&mut self) -> io::Result<()> {
+        Printer::start_group(self)
+    }
+
+    fn print_context(
+        &mut self,
+        path: &Path,
+        line_number: usize,
+        byte_offset: usize,
+        line: &[u8],
+    ) -> io::Result<()> {
+        Printer::print_context(self, path, line_number, byte_offset, line)
+    }
+
+    fn print_match(&mut self, path: &Path, m: &Match) -> io::Result<()> {
+        Printer::print_match(self, path, m)
+    }
+}
+
+pub fn search_reader<R: BufRead, W: Write>(
+    matcher: &Matcher,
+    reader: R,
+    printer: &mut Printer<W>,
+    path: &Path,
+) -> io::Result<usize> {
+    search_lines(matcher, reader, printer, path)
+}
+
+pub(crate) fn search_lines<R: BufRead, O: LineOutput>(
+    matcher: &Matcher,
+    mut reader: R,
+    output: &mut O,
+    path: &Path,
+) -> io::Result<usize> {
+    let before_context = output.before_context();
+    let after_context = output.after_context();
+    let has_context = output.prints_lines() && (before_context > 0 || after_context > 0);
+    // the last few non-matching lines, in case the next line matches and they become -B context
+    let mut before: VecDeque<(usize, usize, Vec<u8>)> = VecDeque::with_capacity(before_context);
+    // how many more lines still belong to the -A context of the last match
+    let mut after_left = 0;
+    let mut last_printed: Option<usize> = None;
+    let mut lexer = matcher.lexer();
+
+    // one buffer reused for every line, rather than allocating a String each time like BufRead::lines would.
+    // It holds raw bytes, and that's what is searched: a Latin-1 file or a stray invalid byte mustn't stop
+    // the search, and the hits and offsets have to point at the right place in the file (see utf8.rs).
+    // Only the printer decodes a line, when it's written out as text.
+    let mut buf = Vec::new();
+    let mut line_number = 0;
+    let mut byte_offset = 0;
+    // the number of selected lines, what -c prints and what decides the exit status
+    let mut count = 0;
+    loop {
+        buf.clear();
+        let read = reader.read_until(b'\n', &mut buf)?;
+        if read == 0 {
+            break;
+        }
+        line_number += 1;
+        // same trimming as str::lines, so both searches see exactly the same lines
+        let line = trim_line(&buf);
+
+        // --only: the hits have to be in the parts of the line the lexer picked out
+        let regions = lexer.as_mut().map(|lexer| matcher.regions(line, lexer));
+        let hits = regions
+            .as_ref()
+            .map(|regions| matcher.find_all_in(line, regions));
+        if hits
+            .as_ref()
+            .map_or_else(|| matcher.is_match(line), |hits| !hits.is_empty())
+        {
+            count += 1;
+            if !output.prints_lines() {
+                if output.stops_at_first_match() {
+                    break;
+                }
+                byte_offset += read;
+                continue;
+            }
+            if has_context {
+                // a new group starts unless this match (or its first -B line) follows straight on from the last line printed
+                let first = before.front().map_or(line_number, |(n, _, _)| *n);
+                if last_printed.is_none_or(|last| first > last + 1) {
+                    output.start_group()?;
+                }
+                for (n, offset, context) in before.drain(..) {
+                    output.print_context(path, n, offset, &context)?;
+                }
+            }
+            let m = Match {
+                line_number,
+                byte_offset,
+                columns: hits.unwrap_or_else(|| matcher.find_all(line)),
+                patterns: match &regions {
+                    Some(regions) => matcher.patterns_in_regions(line, regions),
+                    None => matcher.patterns_in(line),
+                },
+                distance: matcher.distance(line),
+                line,
+            };
+            output.print_match(path, &m)?;
+            if output.stops_at_first_match() {
+                break;
+            }
+            last_printed = Some(line_number);
+            after_left = after_context;
+        } else if after_left > 0 {
+            output.print_context(path, line_number, byte_offset, line)?;
+            last_printed = Some(line_number);
+            after_left -= 1;
+        } else if before_context > 0 {
+            if before.len() == before_context {
+                before.pop_front();
+            }
+            before.push_back((line_number, byte_offset, line.to_vec()));
+        }
+        byte_offset += read;
+    }
+    Ok(count)
+}
+
+// A line without its "\n" or "\r\n".
+pub(crate) fn trim_line(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::OutputMode;
+    use crate::search_matches;
+    use std::path::PathBuf;
+
+    const LOG: &str = "\
+boot
+error: disk
+ok
+ok
+error: net\r
+ok
+ok
+ok
+ok
+error: disk
+error: fan";
+
+    fn in_memory(query: &str, column: bool, json: bool, before: usize, after: usize) -> String {
+        let path = PathBuf::from("log");
+        let matches = search_matches(&Matcher::literal(query, true), LOG);
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&mut out, column, before, after)
+            .with_paths(true)
+            .with_json(json);
+        printer.print_file(&path, LOG, &matches).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn streamed(query: &str, column: bool, json: bool, before: usize, after: usize) -> String {
+        let path = PathBuf::from("log");
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&mut out, column, before, after)
+            .with_paths(true)
+            .with_json(json);
+        search_reader(
+            &Matcher::literal(query, true),
+            LOG.as_bytes(),
+            &mut printer,
+            &path,
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn same_output_as_in_memory_search() {
+        for (before, after) in [(0, 0), (1, 0), (0, 1), (1, 1), (2, 3), (10, 10)] {
+            for (column, json) in [(false, false), (true, false), (false, true)] {
+                for query in ["error", "ok", "boot", "fan", "missing"] {
+                    assert_eq!(
+                        in_memory(query, column, json, before, after),
+                        streamed(query, column, json, before, after),
+                        "query {:?} with -B {} -A {}",
+                        query,
+                        before,
+                        after
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn counts_selected_lines() {
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&mut out, false, 0, 0).with_mode(OutputMode::Count);
+        let count = search_reader(
+            &Matcher::literal("error", true),
+            LOG.as_bytes(),
+            &mut printer,
+            Path::new("log"),
+        )
+        .unwrap();
+
+        assert_eq!(4, count);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn reads_line_by_line() {
+        assert_eq!(
+            "log:2:1:error: disk\nlog:5:1:error: net\nlog:10:1:error: disk\nlog:11:1:error: fan\n",
+            streamed("error", true, false, 0, 0)
+        );
+    }
+}
@@ -0,0 +1,79 @@
+// Lines are searched as the raw bytes they are in the file. A Latin-1 file or a stray invalid byte
+// mustn't stop the search, and every offset (--column, the JSON submatches, absolute_offset) has to
+// count the bytes actually in the file, so nothing is decoded before it's matched: regexes and literal
+// queries run on the bytes themselves. The matchers that need to know about chars (case folding,
+// --fuzzy, -w, the Rust lexer) read them through here, where each invalid sequence is a single U+FFFD
+// spanning its bytes, the same way String::from_utf8_lossy shows it when a line is printed.
+use std::ops::Range;
+
+// The first char of `bytes` and how many bytes it takes up, None when there are none left.
+pub(crate) fn decode(bytes: &[u8]) -> Option<(char, usize)> {
+    // a char is at most 4 bytes, so that's all it takes to tell whether the first one is valid
+    // (and utf8_chunks doesn't get to validate the whole rest of the line every time)
+    let chunk = bytes[..bytes.len().min(4)].utf8_chunks().next()?;
+    match chunk.valid().chars().next() {
+        Some(c) => Some((c, c.len_utf8())),
+        None => Some((char::REPLACEMENT_CHARACTER, chunk.invalid().len())),
+    }
+}
+
+// the char that ends at byte `end`, if there is one
+pub(crate) fn char_before(bytes: &[u8], end: usize) -> Option<char> {
+    char_indices(&bytes[end.saturating_sub(4)..end])
+        .last()
+        .map(|(_, c)| c)
+}
+
+// Like str::char_indices, with the byte range of each char rather than just where it starts.
+pub(crate) fn char_indices(bytes: &[u8]) -> CharIndices<'_> {
+    CharIndices { bytes, offset: 0 }
+}
+
+pub(crate) struct CharIndices<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Iterator for CharIndices<'_> {
+    type Item = (Range<usize>, char);
+
+    fn next(&mut self) -> Option<(Range<usize>, char)> {
+        let (c, len) = decode(&self.bytes[self.offset..])?;
+        let start = self.offset;
+        self.offset += len;
+        Some((start..self.offset, c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_sequences_are_one_replacement_char() {
+        let chars: Vec<(Range<usize>, char)> = char_indices(b"a\xe9\xe2\x82b\xc3\xa9").collect();
+
+        // \xe2\x82 is the start of a 3-byte char cut short, which from_utf8_lossy shows as one U+FFFD too
+        assert_eq!(
+            vec![
+                (0..1, 'a'),
+                (1..2, '\u{FFFD}'),
+                (2..4, '\u{FFFD}'),
+                (4..5, 'b'),
+                (5..7, 'é')
+            ],
+            chars
+        );
+        assert_eq!(
+            "a\u{FFFD}\u{FFFD}bé",
+            String::from_utf8_lossy(b"a\xe9\xe2\x82b\xc3\xa9")
+        );
+    }
+
+    #[test]
+    fn char_before_an_offset() {
+        assert_eq!(Some('é'), char_before(b"caf\xc3\xa9!", 5));
+        assert_eq!(Some('\u{FFFD}'), char_before(b"caf\xe9!", 4));
+        assert_eq!(None, char_before(b"caf", 0));
+    }
+}
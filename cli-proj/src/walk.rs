@@ -0,0 +1,191 @@
+// Turns the paths given on the command line into the list of files to search.
+// Directories are walked recursively (like grep -r); files are returned as-is.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::filter::{Filter, IgnoreFile};
+
+pub fn collect_files(paths: &[String], filter: &Filter) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        // `-` means standard input, like most unix tools, there's nothing on disk to look at
+        if path == "-" {
+            files.push(PathBuf::from(path));
+            continue;
+        }
+        let path = Path::new(path);
+        // a path named explicitly is always searched, even if it's hidden (i.e. `.` or `.config`) or ignored,
+        // the hidden/symlink/ignore rules only apply to what we discover while walking
+        if fs::metadata(path)?.is_dir() {
+            let mut ignores = Vec::new();
+            walk_dir(path, Path::new(""), filter, &mut ignores, &mut files)?;
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    Ok(files)
+}
+
+// `relative` is `dir` relative to the root that was named on the command line, which is what globs and
+// ignore rules match against. `ignores` holds the ignore files of every directory from the root down to `dir`.
+fn walk_dir(
+    dir: &Path,
+    relative: &Path,
+    filter: &Filter,
+    ignores: &mut Vec<IgnoreFile>,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    let loaded = if filter.respects_ignore_files() {
+        IgnoreFile::load(dir, relative)?
+    } else {
+        None
+    };
+    let pushed = loaded.is_some();
+    ignores.extend(loaded);
+
+    // read_dir makes no promises about ordering, so sort to keep the output stable between runs
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        if is_hidden(&entry.file_name()) {
+            continue;
+        }
+        // DirEntry::file_type doesn't follow symlinks, so a link back up the tree can't loop forever
+        let file_type = entry.file_type()?;
+        let entry_relative = relative.join(entry.file_name());
+        if !filter.allows(&entry_relative, file_type.is_dir(), ignores) {
+            continue;
+        }
+        if file_type.is_dir() {
+            walk_dir(&entry.path(), &entry_relative, filter, ignores, files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+
+    // this directory's rules don't apply to its siblings
+    if pushed {
+        ignores.pop();
+    }
+    Ok(())
+}
+
+fn is_hidden(name: &std::ffi::OsStr) -> bool {
+    name.to_str().is_some_and(|name| name.starts_with('.'))
+}
+
+// Same heuristic grep uses: a NUL byte near the start of the file means it's not text.
+// Callers pass the first buffer's worth of the file (BufReader::fill_buf), not the whole thing.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    let head = &bytes[..bytes.len().min(8 * 1024)];
+    head.contains(&0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walks_directories_recursively() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src/nested")).unwrap();
+        fs::write(dir.path().join("b.txt"), "b").unwrap();
+        fs::write(dir.path().join("src/a.rs"), "a").unwrap();
+        fs::write(dir.path().join("src/nested/c.rs"), "c").unwrap();
+
+        let root = dir.path().to_str().unwrap().to_string();
+        let files = collect_files(&[root], &Filter::default()).unwrap();
+
+        assert_eq!(
+            vec![
+                dir.path().join("b.txt"),
+                dir.path().join("src/a.rs"),
+                dir.path().join("src/nested/c.rs"),
+            ],
+            files
+        );
+    }
+
+    #[test]
+    fn skips_hidden_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join(".git/config"), "x").unwrap();
+        fs::write(dir.path().join(".hidden"), "x").unwrap();
+        fs::write(dir.path().join("shown"), "x").unwrap();
+
+        let root = dir.path().to_str().unwrap().to_string();
+        let files = collect_files(&[root], &Filter::default()).unwrap();
+
+        assert_eq!(vec![dir.path().join("shown")], files);
+    }
+
+    #[test]
+    fn keeps_explicit_paths_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("z.txt"), "z").unwrap();
+        fs::write(dir.path().join(".a.txt"), "a").unwrap();
+
+        let z = dir.path().join("z.txt").to_str().unwrap().to_string();
+        let a = dir.path().join(".a.txt").to_str().unwrap().to_string();
+        let files = collect_files(&[z.clone(), a.clone()], &Filter::default()).unwrap();
+
+        assert_eq!(vec![PathBuf::from(z), PathBuf::from(a)], files);
+    }
+
+    #[test]
+    fn respects_ignore_files_while_walking() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("target/debug")).unwrap();
+        fs::create_dir_all(dir.path().join("logs")).unwrap();
+        fs::write(dir.path().join(".gitignore"), "/target\n*.log\n").unwrap();
+        fs::write(dir.path().join("target/debug/out.txt"), "x").unwrap();
+        fs::write(dir.path().join("main.rs"), "x").unwrap();
+        fs::write(dir.path().join("debug.log"), "x").unwrap();
+        fs::write(dir.path().join("logs/.ignore"), "!keep.log\n").unwrap();
+        fs::write(dir.path().join("logs/keep.log"), "x").unwrap();
+        fs::write(dir.path().join("logs/drop.log"), "x").unwrap();
+
+        let roots = vec![dir.path().to_str().unwrap().to_string()];
+        let files = collect_files(&roots, &Filter::default()).unwrap();
+
+        assert_eq!(
+            vec![dir.path().join("logs/keep.log"), dir.path().join("main.rs")],
+            files
+        );
+
+        // --no-ignore finds everything (except hidden files, which is a separate rule)
+        let files = collect_files(&roots, &Filter::new(&[], &[], false).unwrap()).unwrap();
+        assert_eq!(5, files.len());
+    }
+
+    #[test]
+    fn globs_and_excludes_while_walking() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::create_dir_all(dir.path().join("target")).unwrap();
+        fs::write(dir.path().join("src/lib.rs"), "x").unwrap();
+        fs::write(dir.path().join("src/notes.md"), "x").unwrap();
+        fs::write(dir.path().join("target/gen.rs"), "x").unwrap();
+        // an explicitly named file is searched whatever the filters say
+        fs::write(dir.path().join("README.md"), "x").unwrap();
+
+        let root = dir.path().to_str().unwrap().to_string();
+        let readme = dir.path().join("README.md").to_str().unwrap().to_string();
+        let filter = Filter::new(&["*.rs".to_string()], &["target/**".to_string()], true).unwrap();
+        let files = collect_files(&[root, readme], &filter).unwrap();
+
+        assert_eq!(
+            vec![dir.path().join("src/lib.rs"), dir.path().join("README.md")],
+            files
+        );
+    }
+
+    #[test]
+    fn binary_detection() {
+        assert!(!is_binary(b"plain text\n"));
+        assert!(is_binary(b"\x7fELF\x00\x01"));
+    }
+}